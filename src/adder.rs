@@ -4,67 +4,472 @@
 elrond_wasm::imports!();
 elrond_wasm::derive_imports!();
 
+mod pair_proxy;
+
+const MAX_RECENT_REWARD_DEPOSITS: usize = 30;
+const MAX_PERCENTAGE: u64 = 10_000;
+// open-ended reward pool used by depositReward, numbered seasons start at 1
+const DEFAULT_SEASON: u32 = 0;
+
 #[elrond_wasm::contract]
 pub trait Adder {
     #[init]
     fn init(&self, stake_token_opt: OptionalValue<TokenIdentifier>) {
-        let option = stake_token_opt.into_option();
-        if !option.is_none() {
-            let stake_token = option.unwrap();
+        if let Some(stake_token) = stake_token_opt.into_option() {
             self.stake_token().set_if_empty(&stake_token);
         }
-        self.origin_epoch().set_if_empty(&self.blockchain().get_block_epoch());
+        // a fresh deployment has no rewards booked before owed amounts were tracked
+        if self.origin_epoch().is_empty() {
+            self.total_owed_seeding_closed().set(true);
+        }
+        self.origin_epoch().set_if_empty(self.blockchain().get_block_epoch());
     }
 
     #[only_owner]
     #[payable("*")]
     #[endpoint(depositReward)]
     fn deposit_reward(&self) -> BigUint {
+        let payment = self.call_value().egld_or_single_esdt();
+        require!(payment.amount > 0u32, "No payment sent");
+        require!(payment.token_nonce == 0, "NFT and SFT rewards must be deposited as prizes");
+        self.require_allowed_reward(&payment.token_identifier, &payment.amount);
+
+        let caller = self.blockchain().get_caller();
+        self.distribute_reward(DEFAULT_SEASON, &caller, payment)
+    }
+
+    #[only_owner]
+    #[payable("*")]
+    #[endpoint(depositSeasonReward)]
+    fn deposit_season_reward(&self, season_id: u32) -> BigUint {
+        let payment = self.call_value().egld_or_single_esdt();
+        require!(payment.amount > 0u32, "No payment sent");
+        require!(payment.token_nonce == 0, "NFT and SFT rewards must be deposited as prizes");
+        self.require_allowed_reward(&payment.token_identifier, &payment.amount);
+
+        let caller = self.blockchain().get_caller();
+        self.distribute_reward(season_id, &caller, payment)
+    }
+
+    /// Distributes EGLD or several ESDTs at once, returning the reward per ticket of each token.
+    #[only_owner]
+    #[payable("*")]
+    #[endpoint(depositRewards)]
+    fn deposit_rewards(&self) -> MultiValueEncoded<MultiValue2<EgldOrEsdtTokenIdentifier, BigUint>> {
+        let esdt_payments = self.call_value().all_esdt_transfers();
+        let mut payments = ManagedVec::new();
+        // EGLD cannot be sent along with ESDTs in the same call
+        if esdt_payments.is_empty() {
+            let egld_value = self.call_value().egld_value();
+            require!(egld_value > 0u32, "No payment sent");
+            payments.push(RewardAmount {
+                token_identifier: EgldOrEsdtTokenIdentifier::egld(),
+                amount: egld_value,
+            });
+        }
+        for payment in esdt_payments.iter() {
+            require!(payment.amount > 0u32, "No payment sent");
+            require!(payment.token_nonce == 0, "NFT and SFT rewards must be deposited as prizes");
+            let token_identifier = EgldOrEsdtTokenIdentifier::esdt(payment.token_identifier);
+            require!(
+                !payments.iter().any(|other| other.token_identifier == token_identifier),
+                "duplicate reward token"
+            );
+            payments.push(RewardAmount {
+                token_identifier,
+                amount: payment.amount,
+            });
+        }
+        for payment in payments.iter() {
+            self.require_allowed_reward(&payment.token_identifier, &payment.amount);
+        }
+
+        let caller = self.blockchain().get_caller();
+        let rewards_per_ticket = self.distribute_rewards(DEFAULT_SEASON, &caller, &payments);
+        let mut result = MultiValueEncoded::new();
+        for (payment, reward_per_ticket) in payments.iter().zip(rewards_per_ticket.iter()) {
+            result.push((payment.token_identifier, (*reward_per_ticket).clone()).into());
+        }
+        result
+    }
+
+    fn require_allowed_reward(&self, token: &EgldOrEsdtTokenIdentifier, amount: &BigUint) {
+        require!(self.allowed_reward_tokens().contains(token), "reward token is not allowed");
+        require!(amount >= &self.min_reward_deposit(token).get(), "deposit below the minimum for the reward token");
+    }
+
+    /// Allows `token` as a reward, or updates its minimum deposit when it is already allowed.
+    #[only_owner]
+    #[endpoint(allowRewardToken)]
+    fn allow_reward_token(&self, token: EgldOrEsdtTokenIdentifier, min_deposit: BigUint) {
+        self.allowed_reward_tokens().insert(token.clone());
+        self.min_reward_deposit(&token).set(&min_deposit);
+    }
+
+    #[only_owner]
+    #[endpoint(disallowRewardToken)]
+    fn disallow_reward_token(&self, token: EgldOrEsdtTokenIdentifier) {
+        require!(self.allowed_reward_tokens().swap_remove(&token), "reward token is not allowed");
+        self.min_reward_deposit(&token).clear();
+    }
+
+    /// Returns (token, minimum deposit) for every allowed reward token.
+    #[view(getAllowedRewardTokens)]
+    fn get_allowed_reward_tokens(&self) -> MultiValueEncoded<MultiValue2<EgldOrEsdtTokenIdentifier, BigUint>> {
+        let mut allowed_tokens = MultiValueEncoded::new();
+        for token in self.allowed_reward_tokens().iter() {
+            let min_deposit = self.min_reward_deposit(&token).get();
+            allowed_tokens.push((token, min_deposit).into());
+        }
+        allowed_tokens
+    }
+
+    /// Lets whitelisted sponsors, or anyone paying the open sponsoring fee out of the deposit,
+    /// reward the stakers in one of the approved sponsor tokens.
+    #[payable("*")]
+    #[endpoint(sponsorReward)]
+    fn sponsor_reward(&self) -> BigUint {
+        let mut payment = self.call_value().egld_or_single_esdt();
+        require!(payment.amount > 0u32, "No payment sent");
+        require!(payment.token_nonce == 0, "NFT and SFT rewards must be deposited as prizes");
+        require!(self.sponsor_tokens().contains(&payment.token_identifier), "token is not approved for sponsoring");
+        self.require_allowed_reward(&payment.token_identifier, &payment.amount);
+
+        let caller = self.blockchain().get_caller();
+        let mut fee = BigUint::zero();
+        if !self.sponsors().contains(&caller) {
+            require!(!self.sponsor_fee_percentage().is_empty(), "caller is not a sponsor");
+            fee = &payment.amount * self.sponsor_fee_percentage().get() / MAX_PERCENTAGE;
+            if fee > 0u32 {
+                let owner = self.blockchain().get_owner_address();
+                self.send().direct(&owner, &payment.token_identifier, 0, &fee);
+                payment.amount -= &fee;
+            }
+        }
+
+        // tiny deposits would otherwise use up the tickets stakers accrued for the owner's rewards
+        let current_epoch = self.blockchain().get_block_epoch();
+        let last_paid_epoch = self.get_reward_stats_or_default(DEFAULT_SEASON, &payment.token_identifier).last_paid_epoch;
+        let total_tickets = self.get_total_reward_weight(current_epoch, last_paid_epoch);
+        require!(payment.amount >= total_tickets, "sponsored reward below one unit per ticket");
+
+        self.sponsored_reward_event(&caller, &payment.token_identifier, current_epoch, &fee, &payment.amount);
+        self.distribute_reward(DEFAULT_SEASON, &caller, payment)
+    }
+
+    #[only_owner]
+    #[endpoint(addSponsors)]
+    fn add_sponsors(&self, sponsors: MultiValueEncoded<ManagedAddress>) {
+        for sponsor in sponsors {
+            self.sponsors().insert(sponsor);
+        }
+    }
+
+    #[only_owner]
+    #[endpoint(removeSponsors)]
+    fn remove_sponsors(&self, sponsors: MultiValueEncoded<ManagedAddress>) {
+        for sponsor in sponsors {
+            self.sponsors().swap_remove(&sponsor);
+        }
+    }
+
+    #[only_owner]
+    #[endpoint(addSponsorTokens)]
+    fn add_sponsor_tokens(&self, tokens: MultiValueEncoded<EgldOrEsdtTokenIdentifier>) {
+        for token in tokens {
+            self.sponsor_tokens().insert(token);
+        }
+    }
+
+    #[only_owner]
+    #[endpoint(removeSponsorTokens)]
+    fn remove_sponsor_tokens(&self, tokens: MultiValueEncoded<EgldOrEsdtTokenIdentifier>) {
+        for token in tokens {
+            self.sponsor_tokens().swap_remove(&token);
+        }
+    }
+
+    // open sponsoring stays disabled until a fee percentage is set, even a zero one
+    #[only_owner]
+    #[endpoint(setSponsorFeePercentage)]
+    fn set_sponsor_fee_percentage(&self, percentage: u64) {
+        require!(percentage <= MAX_PERCENTAGE, "percentage too high");
+        self.sponsor_fee_percentage().set(percentage);
+    }
+
+    #[only_owner]
+    #[endpoint(disableOpenSponsoring)]
+    fn disable_open_sponsoring(&self) {
+        self.sponsor_fee_percentage().clear();
+    }
+
+    fn distribute_reward(&self, season_id: u32, depositor: &ManagedAddress, payment: EgldOrEsdtTokenPayment) -> BigUint {
+        let reward_amount = RewardAmount {
+            token_identifier: payment.token_identifier,
+            amount: payment.amount,
+        };
+        let rewards_per_ticket = self.distribute_rewards(season_id, depositor, &ManagedVec::from_single_item(reward_amount));
+        (*rewards_per_ticket.get(0)).clone()
+    }
+
+    // the tickets of every staker are only recomputed when a token was last paid at a different epoch
+    fn distribute_rewards(
+        &self,
+        season_id: u32,
+        depositor: &ManagedAddress,
+        payments: &ManagedVec<RewardAmount<Self::Api>>,
+    ) -> ManagedVec<BigUint> {
+        self.require_legacy_stakes_migrated();
+        let current_epoch = self.get_season_reward_epoch(season_id);
+        let mut rewards_per_ticket = ManagedVec::new();
+        let mut tickets_from_epoch = None;
+        let mut stakers = ManagedVec::<Self::Api, ManagedAddress>::new();
+        let mut staker_tickets = ManagedVec::<Self::Api, u64>::new();
+        let mut total_claimable_epochs = 0u64;
+
+        for payment in payments.iter() {
+            let payment_token_name = payment.token_identifier;
+            let mut reward_stats = self.get_reward_stats_or_default(season_id, &payment_token_name);
+            if tickets_from_epoch != Some(reward_stats.last_paid_epoch) {
+                tickets_from_epoch = Some(reward_stats.last_paid_epoch);
+                stakers.clear();
+                staker_tickets.clear();
+                total_claimable_epochs = 0;
+                for address in self.staked_nfts().keys() {
+                    let payable_epochs = self.get_total_payable_epochs(current_epoch, reward_stats.last_paid_epoch, &address);
+                    if payable_epochs == 0 {
+                        continue;
+                    }
+                    // referral bonuses are paid out of the deposit, on top of the stakers' own tickets
+                    total_claimable_epochs += payable_epochs;
+                    if !self.referrer_of(&address).is_empty() {
+                        total_claimable_epochs += self.get_referral_bonus_tickets(payable_epochs);
+                    }
+                    stakers.push(address);
+                    staker_tickets.push(payable_epochs);
+                }
+            }
+            require!(total_claimable_epochs > 0, "no eligible tickets");
+
+            let reward_per_epoch_per_nonce = &payment.amount / &BigUint::from(total_claimable_epochs);
+            for (address, payable_epochs) in stakers.iter().zip(staker_tickets.iter()) {
+                let claimable_amount = &BigUint::from(payable_epochs) * &reward_per_epoch_per_nonce;
+                let payment_info = EgldOrEsdtTokenPayment::new(payment_token_name.clone(), 0, claimable_amount);
+                self.add_claimable_reward(season_id, &address, &payment_info);
+
+                let referral_tickets = self.get_referral_bonus_tickets(payable_epochs);
+                if referral_tickets == 0 || self.referrer_of(&address).is_empty() {
+                    continue;
+                }
+                let referrer = self.referrer_of(&address).get();
+                let referral_amount = &BigUint::from(referral_tickets) * &reward_per_epoch_per_nonce;
+                self.referral_earnings(&referrer, &payment_token_name).update(|earnings| *earnings += &referral_amount);
+                let referral_payment = EgldOrEsdtTokenPayment::new(payment_token_name.clone(), 0, referral_amount);
+                self.add_claimable_reward(season_id, &referrer, &referral_payment);
+            }
+            self.reward_tokens(season_id).insert(payment_token_name.clone());
+
+            self.reward_deposit_history().push(&RewardDepositRecord {
+                season_id,
+                depositor: depositor.clone(),
+                epoch: current_epoch,
+                from_epoch: reward_stats.last_paid_epoch,
+                token_identifier: payment_token_name.clone(),
+                token_nonce: 0,
+                amount: payment.amount.clone(),
+                total_tickets: total_claimable_epochs,
+                reward_per_ticket: reward_per_epoch_per_nonce.clone(),
+                staker_count: stakers.len(),
+            });
+            self.record_recent_reward_deposit(season_id, &payment_token_name, &RecentRewardDeposit {
+                epoch: current_epoch,
+                amount: payment.amount.clone(),
+                tickets: total_claimable_epochs,
+            });
+
+            reward_stats.last_paid_epoch = current_epoch;
+            reward_stats.total_paid_so_far = &reward_stats.total_paid_so_far + &payment.amount;
+            reward_stats.last_reward_payment = payment.amount;
+            reward_stats.last_reward_per_epoch_per_nonce = reward_per_epoch_per_nonce.clone();
+
+            self.reward_payment_info(season_id, &payment_token_name).set(&reward_stats);
+            rewards_per_ticket.push(reward_per_epoch_per_nonce);
+        }
+        rewards_per_ticket
+    }
+
+    // rewards of a season only cover the epochs inside the season
+    fn get_season_reward_epoch(&self, season_id: u32) -> u64 {
         let current_epoch = self.blockchain().get_block_epoch();
+        if season_id == DEFAULT_SEASON {
+            return current_epoch;
+        }
+
+        let season = self.get_season(season_id);
+        require!(current_epoch > season.start_epoch, "season has not started");
+        current_epoch.min(season.end_epoch)
+    }
+
+    #[only_owner]
+    #[endpoint(createSeason)]
+    fn create_season(&self, start_epoch: u64, end_epoch: u64) -> u32 {
+        require!(start_epoch < end_epoch, "season must end after it starts");
+
+        let season_id = self.last_season_id().get() + 1;
+        self.last_season_id().set(season_id);
+        self.seasons(season_id).set(&Season {
+            start_epoch,
+            end_epoch,
+        });
+        season_id
+    }
+
+    #[view(getSeason)]
+    fn get_season(&self, season_id: u32) -> Season {
+        require!(!self.seasons(season_id).is_empty(), "season does not exist");
+        self.seasons(season_id).get()
+    }
+
+    #[only_owner]
+    #[payable("*")]
+    #[endpoint(fundRewardSchedule)]
+    fn fund_reward_schedule(&self, amount_per_period: BigUint, period_epochs: u64, caller_tip: BigUint) {
         let payment = self.call_value().egld_or_single_esdt();
-        require!(&payment.amount > &BigUint::zero(), "No payment sent");
+        require!(payment.amount > 0u32, "No payment sent");
+        require!(payment.token_nonce == 0, "only fungible rewards can be scheduled");
+        require!(amount_per_period > 0u32, "amount per period must be positive");
+        require!(period_epochs > 0, "period must be at least one epoch");
+        self.require_allowed_reward(&payment.token_identifier, &amount_per_period);
+
+        let current_epoch = self.blockchain().get_block_epoch();
+        let schedule_mapper = self.reward_schedule(&payment.token_identifier);
+        let (budget, next_distribution_epoch) = if schedule_mapper.is_empty() {
+            (payment.amount, current_epoch + period_epochs)
+        } else {
+            let schedule = schedule_mapper.get();
+            (schedule.budget + payment.amount, schedule.next_distribution_epoch)
+        };
+
+        schedule_mapper.set(&RewardSchedule {
+            amount_per_period,
+            period_epochs,
+            caller_tip,
+            budget,
+            next_distribution_epoch,
+        });
+    }
+
+    #[only_owner]
+    #[endpoint(cancelRewardSchedule)]
+    fn cancel_reward_schedule(&self, token: EgldOrEsdtTokenIdentifier) {
+        require!(!self.reward_schedule(&token).is_empty(), "no reward schedule for token");
+        let schedule = self.reward_schedule(&token).get();
+        self.reward_schedule(&token).clear();
+        if schedule.budget > 0u32 {
+            let owner = self.blockchain().get_owner_address();
+            self.send().direct(&owner, &token, 0, &schedule.budget);
+        }
+    }
+
+    #[endpoint(distribute)]
+    fn distribute(&self, token: EgldOrEsdtTokenIdentifier) -> BigUint {
+        require!(!self.reward_schedule(&token).is_empty(), "no reward schedule for token");
+        let mut schedule = self.reward_schedule(&token).get();
+        // the token may have been disallowed since the schedule was created
+        self.require_allowed_reward(&token, &schedule.amount_per_period);
+        let current_epoch = self.blockchain().get_block_epoch();
+        require!(current_epoch >= schedule.next_distribution_epoch, "reward period has not elapsed yet");
+        let required_budget = &schedule.amount_per_period + &schedule.caller_tip;
+        require!(schedule.budget >= required_budget, "reward budget exhausted");
+
+        schedule.budget -= &required_budget;
+        schedule.next_distribution_epoch = current_epoch + schedule.period_epochs;
+        self.reward_schedule(&token).set(&schedule);
+
+        let payment = EgldOrEsdtTokenPayment::new(token.clone(), 0, schedule.amount_per_period);
+        // scheduled rewards are funded by the owner
+        let owner = self.blockchain().get_owner_address();
+        let reward_per_epoch_per_nonce = self.distribute_reward(DEFAULT_SEASON, &owner, payment);
+        if schedule.caller_tip > 0u32 {
+            let caller = self.blockchain().get_caller();
+            self.send().direct(&caller, &token, 0, &schedule.caller_tip);
+        }
+        reward_per_epoch_per_nonce
+    }
 
-        let payment_token_name = payment.token_identifier;
-        let mut reward_stats;
-        if self.reward_payment_info(&payment_token_name).is_empty() {
-            reward_stats = RewardPaymentInfo {
-                last_paid_epoch: self.origin_epoch().get(),
+    fn get_reward_stats_or_default(&self, season_id: u32, token: &EgldOrEsdtTokenIdentifier) -> RewardPaymentInfo<Self::Api> {
+        if self.reward_payment_info(season_id, token).is_empty() {
+            let first_epoch = if season_id == DEFAULT_SEASON {
+                self.origin_epoch().get()
+            } else {
+                self.get_season(season_id).start_epoch
+            };
+            return RewardPaymentInfo {
+                last_paid_epoch: first_epoch,
                 total_paid_so_far: BigUint::zero(),
                 last_reward_payment: BigUint::zero(),
                 last_reward_per_epoch_per_nonce: BigUint::zero(),
             };
-        } else {
-            reward_stats = self.reward_payment_info(&payment_token_name).get();
         }
+        self.reward_payment_info(season_id, token).get()
+    }
 
-        let total_claimable_epochs = self.get_total_eligible_tickets(current_epoch, reward_stats.last_paid_epoch);
+    // the default season keeps the storage keys used before seasons existed
+    fn reward_payment_info(&self, season_id: u32, token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<RewardPaymentInfo<Self::Api>> {
+        if season_id == DEFAULT_SEASON {
+            return self.default_reward_payment_info(token);
+        }
+        self.season_reward_payment_info(season_id, token)
+    }
 
-        let reward_per_epoch_per_nonce = &payment.amount / &BigUint::from(total_claimable_epochs);
-        for address in self.staked_nfts().keys() {
-            let payable_epochs = self.get_total_payable_epochs(current_epoch, reward_stats.last_paid_epoch, &address);
-            let claimable_amount = &BigUint::from(payable_epochs) * &reward_per_epoch_per_nonce;
-            let payment_info = EgldOrEsdtTokenPayment::new(payment_token_name.clone(), payment.token_nonce, claimable_amount);
-            self.claimable_rewards(&address).push(&payment_info);
+    fn claimable_rewards(&self, season_id: u32, address: &ManagedAddress) -> VecMapper<EgldOrEsdtTokenPayment> {
+        if season_id == DEFAULT_SEASON {
+            return self.default_claimable_rewards(address);
         }
+        self.season_claimable_rewards(season_id, address)
+    }
 
-        reward_stats.last_paid_epoch = current_epoch;
-        reward_stats.total_paid_so_far = &reward_stats.total_paid_so_far + &payment.amount;
-        reward_stats.last_reward_payment = payment.amount;
-        reward_stats.last_reward_per_epoch_per_nonce = reward_per_epoch_per_nonce.clone();
+    // only fungible rewards are tracked for solvency, owed amounts are not split per nonce
+    fn add_claimable_reward(&self, season_id: u32, address: &ManagedAddress, payment: &EgldOrEsdtTokenPayment) {
+        self.seed_address_owed(address);
+        self.claimable_rewards(season_id, address).push(payment);
+        if payment.token_nonce == 0 {
+            self.owed_reward_tokens().insert(payment.token_identifier.clone());
+            self.total_owed(&payment.token_identifier).update(|owed| *owed += &payment.amount);
+        }
+    }
 
-        self.reward_payment_info(&payment_token_name).set(&reward_stats);
+    fn record_recent_reward_deposit(&self, season_id: u32, token: &EgldOrEsdtTokenIdentifier, deposit: &RecentRewardDeposit<Self::Api>) {
+        let mut recent_deposits = self.recent_reward_deposits(season_id, token);
+        if recent_deposits.len() < MAX_RECENT_REWARD_DEPOSITS {
+            recent_deposits.push(deposit);
+            return;
+        }
 
-        return reward_per_epoch_per_nonce;
+        // buffer is full, overwrite the oldest entry
+        let oldest_index = self.recent_reward_deposits_oldest(season_id, token).get().max(1);
+        recent_deposits.set(oldest_index, deposit);
+        self.recent_reward_deposits_oldest(season_id, token).set(oldest_index % MAX_RECENT_REWARD_DEPOSITS + 1);
     }
 
     #[payable("*")]
     #[endpoint(stake)]
-    fn stake(&self) {
+    fn stake(&self, referrer_opt: OptionalValue<ManagedAddress>) {
         let payment = self.call_value().all_esdt_transfers();
-        require!(payment.len() > 0, "cannot stake nothing");
+        require!(!payment.is_empty(), "cannot stake nothing");
         let stake_token = self.stake_token().get();
         let current_epoch = self.blockchain().get_block_epoch();
         let caller = self.blockchain().get_caller();
+        self.migrate_legacy_stake(&caller);
+
+        // the referrer can only be set by the first stake of an address, later ones ignore it
+        if let Some(referrer) = referrer_opt.into_option() {
+            if !self.known_stakers().contains(&caller) {
+                require!(referrer != caller, "cannot refer yourself");
+                self.referrer_of(&caller).set(&referrer);
+                self.referrals(&referrer).insert(caller.clone());
+            }
+        }
 
         let mut existing_stake;
         if !self.staked_nfts().contains_key(&caller) {
@@ -72,54 +477,395 @@ pub trait Adder {
         } else {
             existing_stake = self.staked_nfts().remove(&caller).unwrap();
         }
+        let mut staked_now = 0u64;
         for nft in payment.iter() {
             require!(nft.token_identifier == stake_token, "one or more NFTs is not eligible for staking");
+            let nonce = nft.token_nonce;
+            require!(nonce > 0, "fungible tokens cannot be staked");
+            self.require_nonce_eligible(&stake_token, nonce);
+            let amount = match nft.amount.to_u64() {
+                Some(amount) => amount,
+                None => sc_panic!("amount of nonce {} is too large", nonce),
+            };
+            self.staked_nonce_amount(&caller, nonce).update(|staked_amount| *staked_amount += amount);
+            self.nonce_stakers(nonce).insert(caller.clone());
             let staked_nft_info = StakedNftInfo {
-                nonce: nft.token_nonce,
+                nonce,
                 stake_epoch: current_epoch,
+                amount,
             };
             existing_stake.push(staked_nft_info);
+            staked_now += amount;
+        }
+
+        let staked_by_address = self.get_staked_units(&existing_stake);
+        self.require_within_staking_limits(staked_now, staked_by_address);
+        self.total_staked().update(|total_staked| *total_staked += staked_now);
+
+        self.staked_nfts().insert(caller.clone(), existing_stake);
+        self.record_stake_checkpoint(&caller);
+    }
+
+    fn get_staked_units(&self, staked_nfts: &ManagedVec<StakedNftInfo>) -> u64 {
+        staked_nfts.iter().map(|nft| nft.amount).sum()
+    }
+
+    /// Converts up to `max_addresses` stakes made before SFT amounts were tracked, returning how many are left.
+    #[only_owner]
+    #[endpoint(migrateLegacyStakes)]
+    fn migrate_legacy_stakes(&self, max_addresses: usize) -> usize {
+        let mut addresses = ManagedVec::<Self::Api, ManagedAddress>::new();
+        for address in self.legacy_staked_nfts().keys().take(max_addresses) {
+            addresses.push(address);
+        }
+        for address in addresses.iter() {
+            self.migrate_legacy_stake(&address);
+        }
+        self.legacy_staked_nfts().len()
+    }
+
+    // every legacy entry is a single unit; its stake history is rebuilt from the stake epochs,
+    // which only covers the units still staked
+    fn migrate_legacy_stake(&self, address: &ManagedAddress) {
+        let legacy_stake = match self.legacy_staked_nfts().remove(address) {
+            Some(legacy_stake) => legacy_stake,
+            None => return,
+        };
+
+        let mut staked_nfts = ManagedVec::new();
+        for nft in legacy_stake.iter() {
+            self.staked_nonce_amount(address, nft.nonce).update(|staked_amount| *staked_amount += 1);
+            self.nonce_stakers(nft.nonce).insert(address.clone());
+            staked_nfts.push(StakedNftInfo {
+                nonce: nft.nonce,
+                stake_epoch: nft.stake_epoch,
+                amount: 1,
+            });
+            self.push_stake_checkpoint(address, StakeCheckpoint {
+                epoch: nft.stake_epoch,
+                staked_count: staked_nfts.len() as u64,
+                set_bonus_weight: self.get_set_bonus_weight(&staked_nfts),
+            });
+        }
+        self.total_staked().update(|total_staked| *total_staked += legacy_stake.len() as u64);
+        self.staked_nfts().insert(address.clone(), staked_nfts);
+        self.known_stakers().insert(address.clone());
+    }
+
+    fn require_legacy_stakes_migrated(&self) {
+        require!(self.legacy_staked_nfts().is_empty(), "legacy stakes must be migrated first");
+    }
+
+    // a limit of zero means no limit
+    fn require_within_staking_limits(&self, staked_now: u64, staked_by_address: u64) {
+        let min_nfts_per_stake = self.min_nfts_per_stake().get();
+        require!(staked_now >= min_nfts_per_stake, "stake below minimum NFTs per stake call");
+
+        let max_nfts_per_address = self.max_nfts_per_address().get();
+        require!(
+            max_nfts_per_address == 0 || staked_by_address <= max_nfts_per_address,
+            "stake exceeds maximum NFTs per address"
+        );
+
+        let max_total_staked = self.max_total_staked().get();
+        require!(
+            max_total_staked == 0 || self.total_staked().get() + staked_now <= max_total_staked,
+            "stake exceeds maximum total staked"
+        );
+    }
+
+    #[only_owner]
+    #[endpoint(setStakingLimits)]
+    fn set_staking_limits(&self, max_nfts_per_address: u64, max_total_staked: u64, min_nfts_per_stake: u64) {
+        self.max_nfts_per_address().set(max_nfts_per_address);
+        self.max_total_staked().set(max_total_staked);
+        self.min_nfts_per_stake().set(min_nfts_per_stake);
+    }
+
+    /// Returns (max units per address, max total staked units, min units per stake call), zero meaning unlimited.
+    #[view(getStakingLimits)]
+    fn get_staking_limits(&self) -> MultiValue3<u64, u64, u64> {
+        (
+            self.max_nfts_per_address().get(),
+            self.max_total_staked().get(),
+            self.min_nfts_per_stake().get(),
+        )
+            .into()
+    }
+
+    fn require_nonce_eligible(&self, stake_token: &TokenIdentifier, nonce: u64) {
+        require!(!self.denied_nonces().contains(&nonce), "nonce {} is denylisted", nonce);
+
+        if !self.eligible_nonce_ranges().is_empty() {
+            let in_range = self.eligible_nonce_ranges().iter().any(|range| range.first <= nonce && nonce <= range.last);
+            require!(in_range, "nonce {} is outside the eligible nonce ranges", nonce);
+        }
+
+        if !self.denied_attribute_prefixes().is_empty() {
+            let sc_address = self.blockchain().get_sc_address();
+            let attributes = self.blockchain().get_esdt_token_data(&sc_address, stake_token, nonce).attributes;
+            for prefix in self.denied_attribute_prefixes().iter() {
+                let has_prefix = attributes.copy_slice(0, prefix.len()).map_or(false, |start| start == prefix);
+                require!(!has_prefix, "nonce {} has denied attributes", nonce);
+            }
+        }
+    }
+
+    #[only_owner]
+    #[endpoint(addEligibleNonceRange)]
+    fn add_eligible_nonce_range(&self, first: u64, last: u64) {
+        require!(first <= last, "invalid nonce range");
+        self.eligible_nonce_ranges().push(&NonceRange { first, last });
+    }
+
+    #[only_owner]
+    #[endpoint(clearEligibleNonceRanges)]
+    fn clear_eligible_nonce_ranges(&self) {
+        self.eligible_nonce_ranges().clear();
+    }
+
+    #[only_owner]
+    #[endpoint(addDeniedNonces)]
+    fn add_denied_nonces(&self, nonces: MultiValueEncoded<u64>) {
+        for nonce in nonces {
+            self.denied_nonces().insert(nonce);
+        }
+    }
+
+    #[only_owner]
+    #[endpoint(removeDeniedNonces)]
+    fn remove_denied_nonces(&self, nonces: MultiValueEncoded<u64>) {
+        for nonce in nonces {
+            self.denied_nonces().swap_remove(&nonce);
         }
+    }
+
+    #[only_owner]
+    #[endpoint(addDeniedAttributePrefix)]
+    fn add_denied_attribute_prefix(&self, prefix: ManagedBuffer) {
+        require!(!prefix.is_empty(), "empty prefix");
+        self.denied_attribute_prefixes().insert(prefix);
+    }
 
-        self.staked_nfts().insert(caller, existing_stake);
+    #[only_owner]
+    #[endpoint(removeDeniedAttributePrefix)]
+    fn remove_denied_attribute_prefix(&self, prefix: ManagedBuffer) {
+        self.denied_attribute_prefixes().swap_remove(&prefix);
     }
 
+    /// Takes (nonce, amount) pairs; the most recently staked units of a nonce are unstaked first.
     #[endpoint(unstake)]
-    fn unstake(&self, nonces_to_unstake_vec: ManagedVec<u64>) {
+    fn unstake(&self, nonces_to_unstake: MultiValueEncoded<MultiValue2<u64, u64>>) {
+        let caller = self.blockchain().get_caller();
+        let unstaked_nfts = self.release_stake(&caller, nonces_to_unstake);
+        self.send().direct_multi(&caller, &unstaked_nfts);
+    }
+
+    /// Unstakes the given (nonce, amount) pairs and claims all rewards in as few transfers as possible.
+    #[endpoint(unstakeAndClaim)]
+    fn unstake_and_claim(&self, nonces_to_unstake: MultiValueEncoded<MultiValue2<u64, u64>>) {
+        let caller = self.blockchain().get_caller();
+        let unstaked_nfts = self.release_stake(&caller, nonces_to_unstake);
+        self.send_rewards_with_stake(&caller, unstaked_nfts);
+    }
+
+    /// Claims all rewards and unstakes every staked NFT, if any are still staked.
+    #[endpoint(exit)]
+    fn exit(&self) {
+        let caller = self.blockchain().get_caller();
+        self.migrate_legacy_stake(&caller);
+        let mut nonces_to_unstake = MultiValueEncoded::new();
+        for nft in self.get_staked_nfts(caller.clone()).iter() {
+            nonces_to_unstake.push((nft.nonce, nft.amount).into());
+        }
+        let unstaked_nfts = if nonces_to_unstake.is_empty() {
+            require!(self.has_claimable_rewards(&caller), "nothing to unstake or claim");
+            ManagedVec::new()
+        } else {
+            self.release_stake(&caller, nonces_to_unstake)
+        };
+        self.send_rewards_with_stake(&caller, unstaked_nfts);
+    }
+
+    fn has_claimable_rewards(&self, address: &ManagedAddress) -> bool {
+        (DEFAULT_SEASON..=self.last_season_id().get()).any(|season_id| !self.claimable_rewards(season_id, address).is_empty())
+    }
+
+    #[only_owner]
+    #[endpoint(addIntegrator)]
+    fn add_integrator(&self, integrator: ManagedAddress) {
+        require!(self.blockchain().is_smart_contract(&integrator), "integrator must be a smart contract");
+        self.integrators().insert(integrator);
+    }
+
+    #[only_owner]
+    #[endpoint(removeIntegrator)]
+    fn remove_integrator(&self, integrator: ManagedAddress) {
+        require!(self.integrators().swap_remove(&integrator), "not an integrator");
+    }
+
+    /// Allows an authorized integrator, e.g. a marketplace, to unstake the caller's NFTs on their behalf.
+    #[endpoint(approveIntegrator)]
+    fn approve_integrator(&self, integrator: ManagedAddress) {
+        require!(self.integrators().contains(&integrator), "not an integrator");
+        let caller = self.blockchain().get_caller();
+        self.integrator_approvals(&caller).insert(integrator);
+    }
+
+    #[endpoint(revokeIntegrator)]
+    fn revoke_integrator(&self, integrator: ManagedAddress) {
+        let caller = self.blockchain().get_caller();
+        self.integrator_approvals(&caller).swap_remove(&integrator);
+    }
+
+    #[view(isIntegratorApproved)]
+    fn is_integrator_approved(&self, staker: ManagedAddress, integrator: ManagedAddress) -> bool {
+        self.integrators().contains(&integrator) && self.integrator_approvals(&staker).contains(&integrator)
+    }
+
+    /// Returns (staked amount, epoch until which a vote locks it) of `nonce` for `staker`.
+    #[view(getStakeStatus)]
+    fn get_stake_status(&self, staker: ManagedAddress, nonce: u64) -> MultiValue2<u64, u64> {
+        let staked_amount = self.staked_nonce_amount(&staker, nonce).get();
+        let locked_until = self.nonce_locked_until(&staker, nonce).get();
+        (staked_amount, locked_until).into()
+    }
+
+    /// Lets an approved integrator unstake (nonce, amount) pairs of `staker` and send them to `receiver`,
+    /// e.g. the buyer of a listed NFT. Pending rewards stay claimable by the staker.
+    #[endpoint(integratorUnstake)]
+    fn integrator_unstake(
+        &self,
+        staker: ManagedAddress,
+        receiver: ManagedAddress,
+        nonces_to_unstake: MultiValueEncoded<MultiValue2<u64, u64>>,
+    ) {
         let caller = self.blockchain().get_caller();
-        require!(self.staked_nfts().contains_key(&caller), "nothing to unstake");
+        require!(self.is_integrator_approved(staker.clone(), caller.clone()), "integrator not approved by staker");
+
+        let unstaked_nfts = self.release_stake(&staker, nonces_to_unstake);
+        self.send().direct_multi(&receiver, &unstaked_nfts);
+        let current_epoch = self.blockchain().get_block_epoch();
+        self.integrator_unstake_event(&caller, &staker, &receiver, current_epoch);
+    }
+
+    // ESDT rewards ride along with the unstaked NFTs unless they go to a reward destination
+    fn send_rewards_with_stake(&self, address: &ManagedAddress, mut payments: ManagedVec<EsdtTokenPayment>) {
+        let receiver = self.get_reward_receiver(address);
+        let (egld_reward, esdt_rewards) = self.take_claimable_rewards(address, &receiver);
+        if &receiver == address {
+            payments.append_vec(esdt_rewards);
+        } else if !esdt_rewards.is_empty() {
+            self.send().direct_multi(&receiver, &esdt_rewards);
+        }
+        if egld_reward > 0u32 {
+            self.send().direct_egld(&receiver, &egld_reward);
+        }
+        if !payments.is_empty() {
+            self.send().direct_multi(address, &payments);
+        }
+    }
+
+    fn release_stake(
+        &self,
+        caller: &ManagedAddress,
+        nonces_to_unstake: MultiValueEncoded<MultiValue2<u64, u64>>,
+    ) -> ManagedVec<EsdtTokenPayment> {
+        self.migrate_legacy_stake(caller);
+        require!(self.staked_nfts().contains_key(caller), "nothing to unstake");
+        require!(!nonces_to_unstake.is_empty(), "no nonces to unstake");
         let stake_token = self.stake_token().get();
-        // let nonces_to_unstake_vec = nonces_to_unstake.to_vec();
+        let current_epoch = self.blockchain().get_block_epoch();
+        let mut staked_nfts = self.staked_nfts().remove(caller).unwrap();
+        let mut unstaked_nfts = ManagedVec::new();
+        let mut unstaked_total = 0u64;
+        for pair in nonces_to_unstake.into_iter() {
+            let (nonce, amount) = pair.into_tuple();
+            require!(amount > 0, "cannot unstake zero units of nonce {}", nonce);
+            let staked_amount = self.staked_nonce_amount(caller, nonce).get();
+            require!(staked_amount > 0, "nonce {} is not staked by caller", nonce);
+            require!(amount <= staked_amount, "not enough units of nonce {} staked", nonce);
+            let locked_until = self.nonce_locked_until(caller, nonce).get();
+            require!(locked_until <= current_epoch, "nonce {} is locked by a vote until epoch {}", nonce, locked_until);
+
+            if amount == staked_amount {
+                self.staked_nonce_amount(caller, nonce).clear();
+                self.nonce_stakers(nonce).swap_remove(caller);
+                self.nonce_locked_until(caller, nonce).clear();
+            } else {
+                self.staked_nonce_amount(caller, nonce).set(staked_amount - amount);
+            }
+
+            let mut left_to_remove = amount;
+            for index in (0..staked_nfts.len()).rev() {
+                if left_to_remove == 0 {
+                    break;
+                }
+                let mut nft = staked_nfts.get(index);
+                if nft.nonce != nonce {
+                    continue;
+                }
+                let removed = nft.amount.min(left_to_remove);
+                nft.amount -= removed;
+                left_to_remove -= removed;
+                let _ = staked_nfts.set(index, &nft);
+            }
+
+            unstaked_total += amount;
+            unstaked_nfts.push(EsdtTokenPayment::new(stake_token.clone(), nonce, BigUint::from(amount)));
+        }
+
+        self.total_staked().update(|total_staked| *total_staked -= unstaked_total);
 
-        let staked_nfts = self.staked_nfts().remove(&caller).unwrap();
         let mut remaining_stake = ManagedVec::new();
         for nft in staked_nfts.iter() {
-            if nonces_to_unstake_vec.contains(&nft.nonce) {
-                self.send().direct_esdt(
-                    &caller,
-                    &stake_token,
-                    nft.nonce,
-                    &BigUint::from(1u32)
-                );
-            } else {
+            if nft.amount > 0 {
                 remaining_stake.push(nft);
             }
         }
         if !remaining_stake.is_empty() {
-            self.staked_nfts().insert(caller, remaining_stake);
+            self.staked_nfts().insert(caller.clone(), remaining_stake);
         }
+        self.record_stake_checkpoint(caller);
+        unstaked_nfts
     }
 
     fn unstake_all_to_address(&self, address: &ManagedAddress) {
         let stake_token = self.stake_token().get();
-        let one = BigUint::from(1u32);
         let staked_nfts = self.staked_nfts().remove(address).unwrap();
         let mut nfts_to_send = ManagedVec::new();
+        self.total_staked().update(|total_staked| *total_staked -= self.get_staked_units(&staked_nfts));
 
         for nft in staked_nfts.iter() {
-            nfts_to_send.push(EsdtTokenPayment::new(stake_token.clone(), nft.nonce, one.clone()));
+            self.staked_nonce_amount(address, nft.nonce).clear();
+            self.nonce_stakers(nft.nonce).swap_remove(address);
+            self.nonce_locked_until(address, nft.nonce).clear();
+            nfts_to_send.push(EsdtTokenPayment::new(stake_token.clone(), nft.nonce, BigUint::from(nft.amount)));
         }
         self.send().direct_multi(address, &nfts_to_send);
+        self.record_stake_checkpoint(address);
+    }
+
+    fn record_stake_checkpoint(&self, address: &ManagedAddress) {
+        let current_epoch = self.blockchain().get_block_epoch();
+        let staked_nfts = self.get_staked_nfts(address.clone());
+        self.push_stake_checkpoint(address, StakeCheckpoint {
+            epoch: current_epoch,
+            staked_count: self.get_staked_units(&staked_nfts),
+            set_bonus_weight: self.get_set_bonus_weight(&staked_nfts),
+        });
+        self.known_stakers().insert(address.clone());
+    }
+
+    // a later checkpoint in the same epoch replaces the previous one
+    fn push_stake_checkpoint(&self, address: &ManagedAddress, checkpoint: StakeCheckpoint) {
+        let mut checkpoints = self.stake_checkpoints(address);
+        let len = checkpoints.len();
+        if len > 0 && checkpoints.get(len).epoch == checkpoint.epoch {
+            checkpoints.set(len, &checkpoint);
+        } else {
+            checkpoints.push(&checkpoint);
+        }
     }
 
     #[endpoint(claimReward)]
@@ -134,61 +880,400 @@ pub trait Adder {
         for address in self.staked_nfts().keys() {
             total_tickets += self.get_total_payable_epochs(current_epoch, last_reward_epoch, &address);
         }
-        return total_tickets;
+        total_tickets
     }
 
-    #[view(getEligibleTickets)]
-    fn get_total_payable_epochs(&self, current_epoch: u64, last_reward_epoch: u64, address: &ManagedAddress) -> u64 {
-        if !self.staked_nfts().contains_key(address) {
-            return 0u64;
-        }
-        let mut payable_epochs = 0u64;
-        let staked_nfts = self.staked_nfts().get(address).unwrap();
-        for nft in staked_nfts.iter() {
-            // can only be staked as follows stake_epoch >= last_reward_epoch in the first month
-            // in the second month, NFTs were staked as follows stake_epoch < last_reward_epoch
-            
-            if last_reward_epoch > nft.stake_epoch { // 20 > 15 sau 20 > 0
-                // staked before the current reward round
-                payable_epochs += current_epoch - last_reward_epoch; // full period of time
-                continue;
+    fn get_total_reward_weight(&self, current_epoch: u64, last_reward_epoch: u64) -> u64 {
+        let mut total_weight = 0;
+        for address in self.staked_nfts().keys() {
+            let tickets = self.get_total_payable_epochs(current_epoch, last_reward_epoch, &address);
+            total_weight += tickets;
+            if !self.referrer_of(&address).is_empty() {
+                total_weight += self.get_referral_bonus_tickets(tickets);
             }
-            payable_epochs += current_epoch - nft.stake_epoch; 
-            // 140 fara check-ul de mai sus
-            // 125 cu check
-            // expected e 100
         }
-        return payable_epochs;
+        total_weight
     }
 
-    #[view(getStakedNfts)]
-    fn get_staked_nfts(&self, address: ManagedAddress) -> ManagedVec<StakedNftInfo> {
-        if !self.staked_nfts().contains_key(&address) {
-            return ManagedVec::new();
+    // own tickets plus the referral bonus tickets earned from referred stakers
+    fn get_reward_weight(&self, current_epoch: u64, last_reward_epoch: u64, address: &ManagedAddress) -> u64 {
+        let mut weight = self.get_total_payable_epochs(current_epoch, last_reward_epoch, address);
+        for referral in self.referrals(address).iter() {
+            let referral_tickets = self.get_total_payable_epochs(current_epoch, last_reward_epoch, &referral);
+            weight += self.get_referral_bonus_tickets(referral_tickets);
         }
-        return self.staked_nfts().get(&address).unwrap();
+        weight
     }
 
-    #[view(getOriginEpoch)]
-    fn get_origin_epoch(&self) -> u64 {
-        return self.origin_epoch().get();
+    fn get_referral_bonus_tickets(&self, referral_tickets: u64) -> u64 {
+        referral_tickets * self.referral_bonus_percentage().get() / MAX_PERCENTAGE
     }
 
-    #[view(getStakeToken)]
-    fn get_stake_token(&self) -> TokenIdentifier {
-        return self.stake_token().get();
+    #[only_owner]
+    #[endpoint(setReferralBonusPercentage)]
+    fn set_referral_bonus_percentage(&self, percentage: u64) {
+        require!(percentage <= MAX_PERCENTAGE, "percentage too high");
+        self.referral_bonus_percentage().set(percentage);
     }
 
-    #[view(getRewardPaymentInfo)]
-    fn get_reward_payment_info(&self, token: EgldOrEsdtTokenIdentifier) -> RewardPaymentInfo<Self::Api> {
-        return self.reward_payment_info(&token).get();
+    #[view(getReferrer)]
+    fn get_referrer(&self, address: ManagedAddress) -> OptionalValue<ManagedAddress> {
+        if self.referrer_of(&address).is_empty() {
+            return OptionalValue::None;
+        }
+        OptionalValue::Some(self.referrer_of(&address).get())
     }
 
+    #[view(getReferralCount)]
+    fn get_referral_count(&self, referrer: ManagedAddress) -> usize {
+        self.referrals(&referrer).len()
+    }
 
-    #[only_owner]
-    #[endpoint(endStaking)]
-    fn end_staking(&self) {
-        let mut gas_left = self.blockchain().get_gas_left();
+    #[view(getEligibleTickets)]
+    fn get_total_payable_epochs(&self, current_epoch: u64, last_reward_epoch: u64, address: &ManagedAddress) -> u64 {
+        if !self.staked_nfts().contains_key(address) {
+            return 0u64;
+        }
+        let mut payable_epochs = 0u64;
+        let staked_nfts = self.staked_nfts().get(address).unwrap();
+        for nft in staked_nfts.iter() {
+            payable_epochs += self.get_nft_payable_epochs(current_epoch, last_reward_epoch, &nft);
+        }
+        payable_epochs + self.get_set_bonus_epochs(current_epoch, last_reward_epoch, &staked_nfts)
+    }
+
+    fn get_nft_payable_epochs(&self, current_epoch: u64, last_reward_epoch: u64, nft: &StakedNftInfo) -> u64 {
+        // can only be staked as follows stake_epoch >= last_reward_epoch in the first month
+        // in the second month, NFTs were staked as follows stake_epoch < last_reward_epoch
+        if nft.stake_epoch >= current_epoch {
+            // staked after the end of the rewarded period (e.g. a past season)
+            return 0;
+        }
+
+        if last_reward_epoch > nft.stake_epoch { // 20 > 15 sau 20 > 0
+            // staked before the current reward round
+            return (current_epoch - last_reward_epoch) * nft.amount; // full period of time
+        }
+        (current_epoch - nft.stake_epoch) * nft.amount
+        // 140 fara check-ul de mai sus
+        // 125 cu check
+        // expected e 100
+    }
+
+    // complete sets earn their bonus percentage on top of the tickets of the NFTs in the set,
+    // counted from the epoch the set became complete
+    fn get_set_bonus_epochs(
+        &self,
+        current_epoch: u64,
+        last_reward_epoch: u64,
+        staked_nfts: &ManagedVec<StakedNftInfo>,
+    ) -> u64 {
+        let mut bonus_epochs = 0u64;
+        for set_id in self.staking_set_ids().iter() {
+            let staking_set = self.staking_sets(set_id).get();
+            let complete_since = match self.get_set_complete_since(&staking_set, staked_nfts) {
+                Some(complete_since) if complete_since < current_epoch => complete_since,
+                _ => continue,
+            };
+
+            let bonus_from_epoch = last_reward_epoch.max(complete_since);
+            let mut set_epochs = 0u64;
+            for nft in staked_nfts.iter() {
+                if staking_set.nonces.contains(&nft.nonce) {
+                    set_epochs += self.get_nft_payable_epochs(current_epoch, bonus_from_epoch, &nft);
+                }
+            }
+            bonus_epochs += set_epochs * staking_set.bonus_percentage / MAX_PERCENTAGE;
+        }
+        bonus_epochs
+    }
+
+    // units are unstaked most recent first, so a nonce has been staked without a break since its
+    // earliest staked unit and the set has been complete since the latest of those epochs
+    fn get_set_complete_since(&self, staking_set: &StakingSet<Self::Api>, staked_nfts: &ManagedVec<StakedNftInfo>) -> Option<u64> {
+        let mut complete_since = 0u64;
+        for nonce in staking_set.nonces.iter() {
+            let first_stake_epoch = staked_nfts.iter().filter(|nft| nft.nonce == nonce).map(|nft| nft.stake_epoch).min()?;
+            complete_since = complete_since.max(first_stake_epoch);
+        }
+        Some(complete_since)
+    }
+
+    // bonus percentage of every complete set times the units staked in it
+    fn get_set_bonus_weight(&self, staked_nfts: &ManagedVec<StakedNftInfo>) -> u64 {
+        let mut set_bonus_weight = 0u64;
+        for set_id in self.staking_set_ids().iter() {
+            let staking_set = self.staking_sets(set_id).get();
+            if self.get_set_complete_since(&staking_set, staked_nfts).is_none() {
+                continue;
+            }
+
+            let set_units: u64 = staked_nfts.iter().filter(|nft| staking_set.nonces.contains(&nft.nonce)).map(|nft| nft.amount).sum();
+            set_bonus_weight += set_units * staking_set.bonus_percentage;
+        }
+        set_bonus_weight
+    }
+
+    fn is_staking_set_complete(&self, address: &ManagedAddress, staking_set: &StakingSet<Self::Api>) -> bool {
+        for nonce in staking_set.nonces.iter() {
+            if self.staked_nonce_amount(address, nonce).get() == 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    #[only_owner]
+    #[endpoint(setStakingSet)]
+    fn set_staking_set(&self, set_id: u32, bonus_percentage: u64, nonces: MultiValueEncoded<u64>) {
+        let nonces = nonces.to_vec();
+        require!(!nonces.is_empty(), "set must contain at least one nonce");
+        require!(bonus_percentage > 0, "bonus percentage must be positive");
+
+        self.staking_sets(set_id).set(&StakingSet {
+            nonces,
+            bonus_percentage,
+        });
+        self.staking_set_ids().insert(set_id);
+    }
+
+    #[only_owner]
+    #[endpoint(removeStakingSet)]
+    fn remove_staking_set(&self, set_id: u32) {
+        require!(self.staking_set_ids().swap_remove(&set_id), "set does not exist");
+        self.staking_sets(set_id).clear();
+    }
+
+    #[view(getStakingSet)]
+    fn get_staking_set(&self, set_id: u32) -> StakingSet<Self::Api> {
+        require!(self.staking_set_ids().contains(&set_id), "set does not exist");
+        self.staking_sets(set_id).get()
+    }
+
+    /// Returns (set id, bonus percentage) for every set fully staked by `address`.
+    #[view(getActiveSetBonuses)]
+    fn get_active_set_bonuses(&self, address: ManagedAddress) -> MultiValueEncoded<MultiValue2<u32, u64>> {
+        let mut active_bonuses = MultiValueEncoded::new();
+        for set_id in self.staking_set_ids().iter() {
+            let staking_set = self.staking_sets(set_id).get();
+            if self.is_staking_set_complete(&address, &staking_set) {
+                active_bonuses.push((set_id, staking_set.bonus_percentage).into());
+            }
+        }
+        active_bonuses
+    }
+
+    #[view(previewReward)]
+    fn preview_reward(&self, address: ManagedAddress, token: EgldOrEsdtTokenIdentifier, amount: BigUint) -> BigUint {
+        let current_epoch = self.blockchain().get_block_epoch();
+        let last_paid_epoch = self.get_reward_stats_or_default(DEFAULT_SEASON, &token).last_paid_epoch;
+        let total_claimable_epochs = self.get_total_reward_weight(current_epoch, last_paid_epoch);
+        if total_claimable_epochs == 0 {
+            return BigUint::zero();
+        }
+
+        let reward_per_epoch_per_nonce = &amount / &BigUint::from(total_claimable_epochs);
+        let payable_epochs = self.get_reward_weight(current_epoch, last_paid_epoch, &address);
+        &BigUint::from(payable_epochs) * &reward_per_epoch_per_nonce
+    }
+
+    /// Share of the next `token` deposit, as (address tickets, total tickets), referral bonuses included.
+    #[view(getTicketShare)]
+    fn get_ticket_share(&self, address: ManagedAddress, token: EgldOrEsdtTokenIdentifier) -> MultiValue2<u64, u64> {
+        let current_epoch = self.blockchain().get_block_epoch();
+        let last_paid_epoch = self.get_reward_stats_or_default(DEFAULT_SEASON, &token).last_paid_epoch;
+        let address_tickets = self.get_reward_weight(current_epoch, last_paid_epoch, &address);
+        let total_tickets = self.get_total_reward_weight(current_epoch, last_paid_epoch);
+        (address_tickets, total_tickets).into()
+    }
+
+    #[view(getRecentRewardDeposits)]
+    fn get_recent_reward_deposits(&self, token: EgldOrEsdtTokenIdentifier) -> ManagedVec<RecentRewardDeposit<Self::Api>> {
+        self.get_season_recent_reward_deposits(DEFAULT_SEASON, token)
+    }
+
+    #[view(getSeasonRecentRewardDeposits)]
+    fn get_season_recent_reward_deposits(&self, season_id: u32, token: EgldOrEsdtTokenIdentifier) -> ManagedVec<RecentRewardDeposit<Self::Api>> {
+        let recent_deposits = self.recent_reward_deposits(season_id, &token);
+        let len = recent_deposits.len();
+        let oldest_index = if len < MAX_RECENT_REWARD_DEPOSITS {
+            1
+        } else {
+            self.recent_reward_deposits_oldest(season_id, &token).get().max(1)
+        };
+
+        let mut ordered_deposits = ManagedVec::new();
+        for i in 0..len {
+            ordered_deposits.push(recent_deposits.get((oldest_index - 1 + i) % len + 1));
+        }
+        ordered_deposits
+    }
+
+    #[view(getLastRewardPerNftPerEpoch)]
+    fn get_last_reward_per_nft_per_epoch(&self, token: EgldOrEsdtTokenIdentifier) -> BigUint {
+        self.get_reward_stats_or_default(DEFAULT_SEASON, &token).last_reward_per_epoch_per_nonce
+    }
+
+    /// Mean reward per NFT per epoch over the last `deposit_count` deposits of `token`.
+    #[view(getAverageRewardPerNftPerEpoch)]
+    fn get_average_reward_per_nft_per_epoch(&self, token: EgldOrEsdtTokenIdentifier, deposit_count: usize) -> BigUint {
+        let recent_deposits = self.get_recent_reward_deposits(token);
+        let len = recent_deposits.len();
+        let count = deposit_count.min(len);
+        if count == 0 {
+            return BigUint::zero();
+        }
+
+        let mut total_reward_per_nft_per_epoch = BigUint::zero();
+        for deposit in recent_deposits.iter().skip(len - count) {
+            if deposit.tickets > 0 {
+                total_reward_per_nft_per_epoch += &deposit.amount / &BigUint::from(deposit.tickets);
+            }
+        }
+        total_reward_per_nft_per_epoch / BigUint::from(count)
+    }
+
+    #[view(getRewardDepositHistoryLength)]
+    fn get_reward_deposit_history_length(&self) -> usize {
+        self.reward_deposit_history().len()
+    }
+
+    /// Returns up to `count` deposit records, starting with the `from`-th deposit (0-based).
+    #[view(getRewardDepositHistory)]
+    fn get_reward_deposit_history(&self, from: usize, count: usize) -> MultiValueEncoded<RewardDepositRecord<Self::Api>> {
+        let history = self.reward_deposit_history();
+        let end = history.len().min(from.saturating_add(count));
+        let mut records = MultiValueEncoded::new();
+        for index in from.saturating_add(1)..=end {
+            records.push(history.get(index));
+        }
+        records
+    }
+
+    /// Ticket-epochs accumulated by `address` between `from_epoch` (inclusive) and `to_epoch` (exclusive),
+    /// including NFTs that have been unstaked since and the bonuses of the sets complete at the time.
+    #[view(getHistoricalEligibleTickets)]
+    fn get_historical_eligible_tickets(&self, address: ManagedAddress, from_epoch: u64, to_epoch: u64) -> u64 {
+        let checkpoints = self.stake_checkpoints(&address);
+        let len = checkpoints.len();
+        let mut tickets = 0u64;
+        let mut set_bonus = 0u64;
+        // the last checkpoint holds until `to_epoch`, far away epochs saturate instead of overflowing
+        for index in 1..=len {
+            let checkpoint = checkpoints.get(index);
+            let end = if index < len {
+                checkpoints.get(index + 1).epoch.min(to_epoch)
+            } else {
+                to_epoch
+            };
+
+            let start = checkpoint.epoch.max(from_epoch);
+            if start < end {
+                let epochs = end - start;
+                tickets = tickets.saturating_add(epochs.saturating_mul(checkpoint.staked_count));
+                set_bonus = set_bonus.saturating_add(epochs.saturating_mul(checkpoint.set_bonus_weight));
+            }
+        }
+        tickets.saturating_add(set_bonus / MAX_PERCENTAGE)
+    }
+
+    #[view(getEligibleTicketsSnapshot)]
+    fn get_eligible_tickets_snapshot(&self, from_epoch: u64, to_epoch: u64) -> MultiValueEncoded<MultiValue2<ManagedAddress, u64>> {
+        let mut snapshot = MultiValueEncoded::new();
+        for address in self.known_stakers().iter() {
+            let tickets = self.get_historical_eligible_tickets(address.clone(), from_epoch, to_epoch);
+            if tickets > 0 {
+                snapshot.push((address, tickets).into());
+            }
+        }
+        snapshot
+    }
+
+    #[view(getStakedNfts)]
+    fn get_staked_nfts(&self, address: ManagedAddress) -> ManagedVec<StakedNftInfo> {
+        if !self.staked_nfts().contains_key(&address) {
+            return ManagedVec::new();
+        }
+        self.staked_nfts().get(&address).unwrap()
+    }
+
+    /// Kept for compatibility with NFT-only staking: the only staker of `nonce`,
+    /// none when it is not staked or its units are split between several stakers.
+    #[view(getNonceOwner)]
+    fn get_nonce_owner(&self, nonce: u64) -> OptionalValue<ManagedAddress> {
+        let stakers = self.nonce_stakers(nonce);
+        if stakers.len() != 1 {
+            return OptionalValue::None;
+        }
+        OptionalValue::Some(stakers.get_by_index(1))
+    }
+
+    #[view(getOriginEpoch)]
+    fn get_origin_epoch(&self) -> u64 {
+        self.origin_epoch().get()
+    }
+
+    #[view(getStakeToken)]
+    fn get_stake_token(&self) -> TokenIdentifier {
+        self.stake_token().get()
+    }
+
+    #[view(getRewardPaymentInfo)]
+    fn get_reward_payment_info(&self, token: EgldOrEsdtTokenIdentifier) -> RewardPaymentInfo<Self::Api> {
+        self.get_season_reward_payment_info(DEFAULT_SEASON, token)
+    }
+
+    /// Unknown tokens get all-zero stats instead of an error.
+    #[view(getSeasonRewardPaymentInfo)]
+    fn get_season_reward_payment_info(&self, season_id: u32, token: EgldOrEsdtTokenIdentifier) -> RewardPaymentInfo<Self::Api> {
+        if self.reward_payment_info(season_id, &token).is_empty() {
+            return RewardPaymentInfo {
+                last_paid_epoch: 0,
+                total_paid_so_far: BigUint::zero(),
+                last_reward_payment: BigUint::zero(),
+                last_reward_per_epoch_per_nonce: BigUint::zero(),
+            };
+        }
+        self.reward_payment_info(season_id, &token).get()
+    }
+
+    /// Returns (token, payment info) of the default reward pool for every reward token ever deposited.
+    #[view(getAllRewardPaymentInfo)]
+    fn get_all_reward_payment_info(&self) -> MultiValueEncoded<MultiValue2<EgldOrEsdtTokenIdentifier, RewardPaymentInfo<Self::Api>>> {
+        let mut all_payment_info = MultiValueEncoded::new();
+        for token in self.reward_tokens(DEFAULT_SEASON).iter() {
+            let payment_info = self.get_reward_payment_info(token.clone());
+            all_payment_info.push((token, payment_info).into());
+        }
+        all_payment_info
+    }
+
+    #[view(getRewardTokens)]
+    fn get_reward_tokens(&self) -> MultiValueEncoded<EgldOrEsdtTokenIdentifier> {
+        let mut reward_tokens = MultiValueEncoded::new();
+        for token in self.reward_tokens(DEFAULT_SEASON).iter() {
+            reward_tokens.push(token);
+        }
+        reward_tokens
+    }
+
+    /// Records tokens deposited before the deposited tokens were listed, so they show up in
+    /// getRewardTokens and getAllRewardPaymentInfo.
+    #[only_owner]
+    #[endpoint(backfillRewardTokens)]
+    fn backfill_reward_tokens(&self, tokens: MultiValueEncoded<EgldOrEsdtTokenIdentifier>) {
+        for token in tokens {
+            require!(!self.reward_payment_info(DEFAULT_SEASON, &token).is_empty(), "token was never deposited as a reward");
+            self.reward_tokens(DEFAULT_SEASON).insert(token);
+        }
+    }
+
+
+    #[only_owner]
+    #[endpoint(endStaking)]
+    fn end_staking(&self) {
+        self.require_legacy_stakes_migrated();
+        let mut gas_left = self.blockchain().get_gas_left();
         for address in self.staked_nfts().keys() {
             if gas_left < 5_000_000 {
                 break;
@@ -202,23 +1287,764 @@ pub trait Adder {
         }
     }
 
+    #[endpoint(claimSeasonReward)]
+    fn claim_season_reward(&self, season_id: u32) {
+        let caller = self.blockchain().get_caller();
+        let receiver = self.get_reward_receiver(&caller);
+        require!(self.send_season_rewards(season_id, &caller, &receiver), "no rewards to claim");
+    }
+
+    /// One-off claim of every season's rewards to `receiver`, ignoring the reward destination.
+    #[endpoint(claimRewardTo)]
+    fn claim_reward_to(&self, receiver: ManagedAddress) {
+        let caller = self.blockchain().get_caller();
+        self.claim_rewards_to_receiver(&caller, &receiver);
+    }
+
+    #[endpoint(setRewardDestination)]
+    fn set_reward_destination(&self, destination_opt: OptionalValue<ManagedAddress>) {
+        let caller = self.blockchain().get_caller();
+        match destination_opt.into_option() {
+            Some(destination) if destination != caller => self.reward_destination(&caller).set(&destination),
+            _ => self.reward_destination(&caller).clear(),
+        }
+    }
+
+    #[view(getRewardDestination)]
+    fn get_reward_destination(&self, address: ManagedAddress) -> OptionalValue<ManagedAddress> {
+        if self.reward_destination(&address).is_empty() {
+            return OptionalValue::None;
+        }
+        OptionalValue::Some(self.reward_destination(&address).get())
+    }
+
+    fn get_reward_receiver(&self, address: &ManagedAddress) -> ManagedAddress {
+        if self.reward_destination(address).is_empty() {
+            return address.clone();
+        }
+        self.reward_destination(address).get()
+    }
+
     fn claim_reward_to_address(&self, address: &ManagedAddress) {
-        require!(!self.claimable_rewards(address).is_empty(), "no rewards to claim");
-        for reward in self.claimable_rewards(address).iter() {
+        let receiver = self.get_reward_receiver(address);
+        self.claim_rewards_to_receiver(address, &receiver);
+    }
+
+    fn claim_rewards_to_receiver(&self, address: &ManagedAddress, receiver: &ManagedAddress) {
+        let mut claimed_any = false;
+        for season_id in DEFAULT_SEASON..=self.last_season_id().get() {
+            claimed_any |= self.send_season_rewards(season_id, address, receiver);
+        }
+        require!(claimed_any, "no rewards to claim");
+    }
+
+    fn send_season_rewards(&self, season_id: u32, address: &ManagedAddress, receiver: &ManagedAddress) -> bool {
+        if self.claimable_rewards(season_id, address).is_empty() {
+            return false;
+        }
+        if receiver != address {
+            let current_epoch = self.blockchain().get_block_epoch();
+            self.reward_redirected_event(address, receiver, season_id, current_epoch);
+        }
+        for reward in self.claimable_rewards(season_id, address).iter() {
             self.send().direct(
-                address,
+                receiver,
                 &reward.token_identifier,
                 reward.token_nonce,
                 &reward.amount
             );
+            self.record_claimed_reward(address, &reward);
         }
-        self.claimable_rewards(address).clear();
+        self.claimable_rewards(season_id, address).clear();
+        true
     }
 
+    // books the rewards of every season as claimed and returns them as (EGLD amount, ESDT payments), sending nothing
+    fn take_claimable_rewards(&self, address: &ManagedAddress, receiver: &ManagedAddress) -> (BigUint, ManagedVec<EsdtTokenPayment>) {
+        let mut egld_reward = BigUint::zero();
+        let mut esdt_rewards = ManagedVec::new();
+        for season_id in DEFAULT_SEASON..=self.last_season_id().get() {
+            if self.claimable_rewards(season_id, address).is_empty() {
+                continue;
+            }
+            if receiver != address {
+                let current_epoch = self.blockchain().get_block_epoch();
+                self.reward_redirected_event(address, receiver, season_id, current_epoch);
+            }
+            for reward in self.claimable_rewards(season_id, address).iter() {
+                self.record_claimed_reward(address, &reward);
+                if reward.token_identifier.is_egld() {
+                    egld_reward += reward.amount;
+                } else {
+                    esdt_rewards.push(EsdtTokenPayment::new(reward.token_identifier.unwrap_esdt(), reward.token_nonce, reward.amount));
+                }
+            }
+            self.claimable_rewards(season_id, address).clear();
+        }
+        (egld_reward, esdt_rewards)
+    }
+
+    #[endpoint(setPreferredRewardToken)]
+    fn set_preferred_reward_token(&self, token_opt: OptionalValue<TokenIdentifier>) {
+        let caller = self.blockchain().get_caller();
+        match token_opt.into_option() {
+            Some(token) => self.preferred_reward_token(&caller).set(&token),
+            None => self.preferred_reward_token(&caller).clear(),
+        }
+    }
+
+    #[view(getPreferredRewardToken)]
+    fn get_preferred_reward_token(&self, address: ManagedAddress) -> OptionalValue<TokenIdentifier> {
+        if self.preferred_reward_token(&address).is_empty() {
+            return OptionalValue::None;
+        }
+        OptionalValue::Some(self.preferred_reward_token(&address).get())
+    }
+
+    #[only_owner]
+    #[endpoint(setSwapPair)]
+    fn set_swap_pair(&self, token_in: TokenIdentifier, token_out: TokenIdentifier, pair_address: ManagedAddress) {
+        require!(token_in != token_out, "cannot swap a token to itself");
+        require!(self.blockchain().is_smart_contract(&pair_address), "pair must be a smart contract");
+        self.swap_pair(&token_in, &token_out).set(&pair_address);
+    }
+
+    #[only_owner]
+    #[endpoint(removeSwapPair)]
+    fn remove_swap_pair(&self, token_in: TokenIdentifier, token_out: TokenIdentifier) {
+        self.swap_pair(&token_in, &token_out).clear();
+    }
+
+    /// Claims every season's `token` rewards and swaps them into the caller's preferred reward token
+    /// through the configured DEX pair. If the swap fails, e.g. because less than `amount_out_min`
+    /// would be received, or the pair does not send back the preferred token, the original tokens are sent instead.
+    #[endpoint(claimSwappedReward)]
+    fn claim_swapped_reward(&self, token: TokenIdentifier, amount_out_min: BigUint) {
+        let caller = self.blockchain().get_caller();
+        require!(!self.preferred_reward_token(&caller).is_empty(), "no preferred reward token set");
+        let preferred_token = self.preferred_reward_token(&caller).get();
+        require!(token != preferred_token, "reward is already in the preferred token");
+        require!(!self.swap_pair(&token, &preferred_token).is_empty(), "no swap pair for token");
+        let pair_address = self.swap_pair(&token, &preferred_token).get();
+
+        let receiver = self.get_reward_receiver(&caller);
+        let amount = self.take_claimable_token_rewards(&caller, &receiver, &token);
+        require!(amount > 0u32, "no rewards to claim");
+
+        self.pair_proxy(pair_address)
+            .swap_tokens_fixed_input(preferred_token.clone(), amount_out_min.clone())
+            .add_esdt_token_transfer(token.clone(), 0, amount.clone())
+            .async_call()
+            .with_callback(self.callbacks().swap_reward_callback(receiver, token, amount, preferred_token, amount_out_min))
+            .call_and_exit();
+    }
+
+    #[callback]
+    fn swap_reward_callback(
+        &self,
+        receiver: ManagedAddress,
+        token: TokenIdentifier,
+        amount: BigUint,
+        preferred_token: TokenIdentifier,
+        amount_out_min: BigUint,
+        #[call_result] result: ManagedAsyncCallResult<EsdtTokenPayment>,
+    ) {
+        // only what actually came back with the callback is paid out, whatever the pair reports
+        let received = self.call_value().all_esdt_transfers();
+        let swapped_opt = match result {
+            ManagedAsyncCallResult::Ok(_) if received.len() == 1 => Some(received.get(0)),
+            _ => None,
+        };
+        match swapped_opt {
+            Some(swapped)
+                if swapped.token_identifier == preferred_token
+                    && swapped.token_nonce == 0
+                    && swapped.amount >= amount_out_min =>
+            {
+                self.send().direct_esdt(&receiver, &swapped.token_identifier, 0, &swapped.amount);
+                self.reward_swapped_event(&receiver, &token, &amount, &swapped.token_identifier, &swapped.amount);
+            },
+            _ => {
+                // the pair sends the input back when the swap fails, otherwise the reward is refunded as is
+                self.send().direct_esdt(&receiver, &token, 0, &amount);
+                self.reward_swap_failed_event(&receiver, &token, &amount);
+            },
+        }
+    }
+
+    // books the `token` rewards of every season as claimed and returns their sum, sending nothing
+    fn take_claimable_token_rewards(&self, address: &ManagedAddress, receiver: &ManagedAddress, token: &TokenIdentifier) -> BigUint {
+        let reward_token = EgldOrEsdtTokenIdentifier::esdt(token.clone());
+        let mut amount = BigUint::zero();
+        for season_id in DEFAULT_SEASON..=self.last_season_id().get() {
+            let mut claimable_rewards = self.claimable_rewards(season_id, address);
+            let mut season_amount = BigUint::zero();
+            for index in (1..=claimable_rewards.len()).rev() {
+                let reward = claimable_rewards.get(index);
+                if reward.token_identifier != reward_token || reward.token_nonce != 0 {
+                    continue;
+                }
+                self.record_claimed_reward(address, &reward);
+                season_amount += reward.amount;
+                claimable_rewards.swap_remove(index);
+            }
+            if season_amount > 0u32 && receiver != address {
+                let current_epoch = self.blockchain().get_block_epoch();
+                self.reward_redirected_event(address, receiver, season_id, current_epoch);
+            }
+            amount += season_amount;
+        }
+        amount
+    }
+
+    #[proxy]
+    fn pair_proxy(&self, sc_address: ManagedAddress) -> pair_proxy::Proxy<Self::Api>;
+
+    fn record_claimed_reward(&self, address: &ManagedAddress, reward: &EgldOrEsdtTokenPayment) {
+        self.lifetime_claimed(address, &reward.token_identifier).update(|claimed| *claimed += &reward.amount);
+        self.claimed_reward_tokens(address).insert(reward.token_identifier.clone());
+        self.total_claimed(&reward.token_identifier).update(|claimed| *claimed += &reward.amount);
+        if reward.token_nonce == 0 {
+            self.seed_address_owed(address);
+            let mut owed = self.total_owed(&reward.token_identifier).get();
+            require!(owed >= reward.amount, "claimed reward exceeds the owed total");
+            owed -= &reward.amount;
+            self.total_owed(&reward.token_identifier).set(&owed);
+        }
+    }
+
+    /// Adds the rewards that were already claimable by `addresses` before owed amounts were tracked
+    /// to the owed totals, every address being counted once. Only possible until closeTotalOwedSeeding.
+    #[only_owner]
+    #[endpoint(seedTotalOwed)]
+    fn seed_total_owed(&self, addresses: MultiValueEncoded<ManagedAddress>) {
+        require!(!self.total_owed_seeding_closed().get(), "owed totals are already seeded");
+        for address in addresses {
+            self.seed_address_owed(&address);
+        }
+    }
+
+    /// Marks the owed totals as complete once every address with rewards from before the upgrade is seeded.
+    /// Claiming rewards that were never seeded fails afterwards.
+    #[only_owner]
+    #[endpoint(closeTotalOwedSeeding)]
+    fn close_total_owed_seeding(&self) {
+        self.total_owed_seeding_closed().set(true);
+    }
+
+    // while seeding is open an address is seeded before it is credited or claims anything,
+    // so its older rewards are never mixed up with the ones booked since
+    fn seed_address_owed(&self, address: &ManagedAddress) {
+        if self.total_owed_seeding_closed().get() || !self.owed_seeded_addresses().insert(address.clone()) {
+            return;
+        }
+        for reward in self.claimable_rewards(DEFAULT_SEASON, address).iter() {
+            if reward.token_nonce == 0 {
+                self.owed_reward_tokens().insert(reward.token_identifier.clone());
+                self.total_owed(&reward.token_identifier).update(|owed| *owed += &reward.amount);
+            }
+        }
+    }
+
+    #[view(getClaimableRewards)]
+    fn get_claimable_rewards(&self, address: ManagedAddress) -> MultiValueEncoded<EgldOrEsdtTokenPayment> {
+        let mut claimable_rewards = MultiValueEncoded::new();
+        for season_id in DEFAULT_SEASON..=self.last_season_id().get() {
+            for reward in self.claimable_rewards(season_id, &address).iter() {
+                claimable_rewards.push(reward);
+            }
+        }
+        claimable_rewards
+    }
+
+    #[view(getSeasonClaimableRewards)]
+    fn get_season_claimable_rewards(&self, season_id: u32, address: ManagedAddress) -> MultiValueEncoded<EgldOrEsdtTokenPayment> {
+        let mut claimable_rewards = MultiValueEncoded::new();
+        for reward in self.claimable_rewards(season_id, &address).iter() {
+            claimable_rewards.push(reward);
+        }
+        claimable_rewards
+    }
+
+    /// Returns (token, total owed to stakers, contract balance not set aside for a reward schedule)
+    /// for every fungible token ever owed as a reward, prizes included. Owed totals of an upgraded contract are only complete
+    /// once isTotalOwedSeedingClosed.
+    #[view(getSolvencyReport)]
+    fn get_solvency_report(&self) -> MultiValueEncoded<MultiValue3<EgldOrEsdtTokenIdentifier, BigUint, BigUint>> {
+        let mut report = MultiValueEncoded::new();
+        for token in self.owed_reward_tokens().iter() {
+            let owed = self.total_owed(&token).get();
+            let balance = self.get_balance_available_for_rewards(&token);
+            report.push((token, owed, balance).into());
+        }
+        report
+    }
+
+    // scheduled budgets are not owed to anyone yet; the prize inventory only holds NFT and SFT nonces,
+    // which are not part of the fungible balance
+    fn get_balance_available_for_rewards(&self, token: &EgldOrEsdtTokenIdentifier) -> BigUint {
+        let balance = self.blockchain().get_sc_balance(token, 0);
+        if self.reward_schedule(token).is_empty() {
+            return balance;
+        }
+        let budget = self.reward_schedule(token).get().budget;
+        if balance > budget {
+            balance - budget
+        } else {
+            BigUint::zero()
+        }
+    }
+
+    #[view(getLifetimeEarnings)]
+    fn get_lifetime_earnings(&self, address: ManagedAddress) -> MultiValueEncoded<MultiValue2<EgldOrEsdtTokenIdentifier, BigUint>> {
+        let mut earnings = MultiValueEncoded::new();
+        for token in self.claimed_reward_tokens(&address).iter() {
+            let claimed = self.lifetime_claimed(&address, &token).get();
+            earnings.push((token, claimed).into());
+        }
+        earnings
+    }
+
+    /// Returns (total deposited, total claimed) for `token` over the lifetime of the contract.
+    #[view(getRewardTotals)]
+    fn get_reward_totals(&self, token: EgldOrEsdtTokenIdentifier) -> MultiValue2<BigUint, BigUint> {
+        let mut total_deposited = BigUint::zero();
+        for season_id in DEFAULT_SEASON..=self.last_season_id().get() {
+            if !self.reward_payment_info(season_id, &token).is_empty() {
+                total_deposited += self.reward_payment_info(season_id, &token).get().total_paid_so_far;
+            }
+        }
+        let total_claimed = self.total_claimed(&token).get();
+        (total_deposited, total_claimed).into()
+    }
+
+    #[only_owner]
+    #[payable("*")]
+    #[endpoint(raffle)]
+    fn raffle(&self, winners_count: usize) -> u64 {
+        let payment = self.call_value().egld_or_single_esdt();
+        require!(payment.amount > 0u32, "No payment sent");
+        require!(winners_count > 0, "must draw at least one winner");
+        let stake_token = EgldOrEsdtTokenIdentifier::esdt(self.stake_token().get());
+        require!(payment.token_identifier != stake_token, "the stake token cannot be a prize");
+        self.require_legacy_stakes_migrated();
+        let prize_amount = &payment.amount / &BigUint::from(winners_count);
+        require!(prize_amount > 0u32, "prize too small for the number of winners");
+
+        let current_epoch = self.blockchain().get_block_epoch();
+        let last_raffle_epoch = if self.last_raffle_epoch().is_empty() {
+            self.origin_epoch().get()
+        } else {
+            self.last_raffle_epoch().get()
+        };
+
+        let mut candidates = ManagedVec::new();
+        let mut weights = ManagedVec::new();
+        for address in self.staked_nfts().keys() {
+            let tickets = self.get_total_payable_epochs(current_epoch, last_raffle_epoch, &address);
+            if tickets > 0 {
+                candidates.push(address);
+                weights.push(tickets);
+            }
+        }
+        require!(winners_count <= candidates.len(), "not enough eligible stakers");
+
+        let winners = self.draw_weighted_winners(&candidates, weights, winners_count);
+        let prize = EgldOrEsdtTokenPayment::new(payment.token_identifier, payment.token_nonce, prize_amount);
+        // the first winner also receives whatever does not split evenly
+        let remainder = &payment.amount % &BigUint::from(winners_count);
+        for (index, winner) in winners.iter().enumerate() {
+            if index == 0 && remainder > 0u32 {
+                let mut first_prize = prize.clone();
+                first_prize.amount += &remainder;
+                self.add_claimable_reward(DEFAULT_SEASON, &winner, &first_prize);
+            } else {
+                self.add_claimable_reward(DEFAULT_SEASON, &winner, &prize);
+            }
+        }
+
+        let raffle_id = self.last_raffle_id().get() + 1;
+        self.last_raffle_id().set(raffle_id);
+        self.last_raffle_epoch().set(current_epoch);
+        self.raffles(raffle_id).set(&RaffleInfo {
+            epoch: current_epoch,
+            prize,
+            remainder,
+            winners,
+        });
+        raffle_id
+    }
+
+    // draws without replacement, each candidate's chance being proportional to its weight
+    fn draw_weighted_winners(
+        &self,
+        candidates: &ManagedVec<ManagedAddress>,
+        mut weights: ManagedVec<u64>,
+        winners_count: usize,
+    ) -> ManagedVec<ManagedAddress> {
+        let mut total_weight: u64 = weights.iter().sum();
+        let mut rng = RandomnessSource::<Self::Api>::new();
+        let mut winners = ManagedVec::new();
+        for _ in 0..winners_count {
+            let mut ticket = rng.next_u64_in_range(0, total_weight);
+            let mut winner_index = 0;
+            for (index, weight) in weights.iter().enumerate() {
+                if ticket < weight {
+                    winner_index = index;
+                    break;
+                }
+                ticket -= weight;
+            }
+
+            winners.push((*candidates.get(winner_index)).clone());
+            total_weight -= weights.get(winner_index);
+            let _ = weights.set(winner_index, &0);
+        }
+        winners
+    }
+
+    #[view(getRaffle)]
+    fn get_raffle(&self, raffle_id: u64) -> RaffleInfo<Self::Api> {
+        require!(!self.raffles(raffle_id).is_empty(), "raffle does not exist");
+        self.raffles(raffle_id).get()
+    }
+
+    #[only_owner]
+    #[payable("*")]
+    #[endpoint(depositPrizes)]
+    fn deposit_prizes(&self) {
+        let payments = self.call_value().all_esdt_transfers();
+        require!(!payments.is_empty(), "No payment sent");
+        let stake_token = self.stake_token().get();
+        for payment in payments.iter() {
+            require!(payment.token_nonce > 0, "prizes must be NFTs or SFTs");
+            require!(payment.token_identifier != stake_token, "the stake token cannot be a prize");
+            self.prize_inventory().push(&payment);
+        }
+    }
+
+    /// Hands out `items_count` single units from the prize inventory, most recently deposited first,
+    /// to the stakers holding tickets earned since the previous prize distribution.
+    #[only_owner]
+    #[endpoint(distributePrizes)]
+    fn distribute_prizes(&self, mode: PrizeDistributionMode, items_count: usize) {
+        require!(items_count > 0, "must distribute at least one prize");
+        self.require_legacy_stakes_migrated();
+        let mut inventory_units = BigUint::zero();
+        for item in self.prize_inventory().iter() {
+            inventory_units += item.amount;
+        }
+        require!(BigUint::from(items_count) <= inventory_units, "not enough prizes in inventory");
+
+        let current_epoch = self.blockchain().get_block_epoch();
+        let last_distribution_epoch = if self.last_prize_distribution_epoch().is_empty() {
+            self.origin_epoch().get()
+        } else {
+            self.last_prize_distribution_epoch().get()
+        };
+
+        let mut candidates = ManagedVec::new();
+        let mut weights = ManagedVec::new();
+        for address in self.staked_nfts().keys() {
+            let tickets = self.get_total_payable_epochs(current_epoch, last_distribution_epoch, &address);
+            if tickets > 0 {
+                candidates.push(address);
+                weights.push(tickets);
+            }
+        }
+        require!(!candidates.is_empty(), "no eligible tickets");
+
+        let winners = match mode {
+            PrizeDistributionMode::TopHolders => self.rank_top_holders(&candidates, weights, items_count),
+            PrizeDistributionMode::WeightedRandom => {
+                // every staker wins at most once per round
+                let mut winners = ManagedVec::new();
+                while winners.len() < items_count {
+                    let round_count = (items_count - winners.len()).min(candidates.len());
+                    winners.append_vec(self.draw_weighted_winners(&candidates, weights.clone(), round_count));
+                }
+                winners
+            },
+        };
+
+        for winner in winners.iter() {
+            let prize = self.take_prize_unit();
+            self.add_claimable_reward(DEFAULT_SEASON, &winner, &prize);
+        }
+        self.last_prize_distribution_epoch().set(current_epoch);
+    }
+
+    // item `i` goes to the `i`-th largest ticket holder, wrapping around when there are more items than holders
+    fn rank_top_holders(
+        &self,
+        candidates: &ManagedVec<ManagedAddress>,
+        mut weights: ManagedVec<u64>,
+        items_count: usize,
+    ) -> ManagedVec<ManagedAddress> {
+        let mut ranking = ManagedVec::<Self::Api, ManagedAddress>::new();
+        for _ in 0..candidates.len() {
+            let mut top_index = 0;
+            for (index, weight) in weights.iter().enumerate() {
+                if weight > weights.get(top_index) {
+                    top_index = index;
+                }
+            }
+            ranking.push((*candidates.get(top_index)).clone());
+            let _ = weights.set(top_index, &0);
+        }
+
+        let mut winners = ManagedVec::new();
+        for item_index in 0..items_count {
+            winners.push((*ranking.get(item_index % ranking.len())).clone());
+        }
+        winners
+    }
+
+    fn take_prize_unit(&self) -> EgldOrEsdtTokenPayment {
+        let mut inventory = self.prize_inventory();
+        let last_index = inventory.len();
+        let mut item = inventory.get(last_index);
+        item.amount -= 1u32;
+        if item.amount == 0u32 {
+            inventory.swap_remove(last_index);
+        } else {
+            inventory.set(last_index, &item);
+        }
+        EgldOrEsdtTokenPayment::new(EgldOrEsdtTokenIdentifier::esdt(item.token_identifier), item.token_nonce, BigUint::from(1u32))
+    }
+
+    #[only_owner]
+    #[endpoint(createProposal)]
+    fn create_proposal(
+        &self,
+        description: ManagedBuffer,
+        end_epoch: u64,
+        weight_by_duration: bool,
+        options: MultiValueEncoded<ManagedBuffer>,
+    ) -> u64 {
+        require!(end_epoch > self.blockchain().get_block_epoch(), "proposal must end in the future");
+        let options = options.to_vec();
+        require!(options.len() >= 2, "proposal needs at least two options");
+
+        let proposal_id = self.last_proposal_id().get() + 1;
+        self.last_proposal_id().set(proposal_id);
+        self.proposals(proposal_id).set(&Proposal {
+            description,
+            options,
+            end_epoch,
+            weight_by_duration,
+        });
+        proposal_id
+    }
+
+    #[endpoint(vote)]
+    fn vote(&self, proposal_id: u64, option_index: usize) {
+        require!(!self.proposals(proposal_id).is_empty(), "proposal does not exist");
+        let proposal = self.proposals(proposal_id).get();
+        let current_epoch = self.blockchain().get_block_epoch();
+        require!(current_epoch < proposal.end_epoch, "proposal has ended");
+        require!(option_index < proposal.options.len(), "invalid option");
+
+        let caller = self.blockchain().get_caller();
+        require!(!self.proposal_voters(proposal_id).contains(&caller), "already voted");
+        self.migrate_legacy_stake(&caller);
+        let weight = self.get_voting_weight(caller.clone(), proposal.weight_by_duration);
+        require!(weight > 0, "only stakers can vote");
+
+        // NFTs backing the vote stay staked until the proposal ends
+        for nft in self.get_staked_nfts(caller.clone()).iter() {
+            self.nonce_locked_until(&caller, nft.nonce).update(|locked_until| *locked_until = (*locked_until).max(proposal.end_epoch));
+        }
+        self.proposal_voters(proposal_id).insert(caller);
+        self.proposal_votes(proposal_id, option_index).update(|votes| *votes += weight);
+    }
+
+    /// One vote per staked unit, or one vote per unit per epoch staked (counting the current one) when weighted by duration.
+    #[view(getVotingWeight)]
+    fn get_voting_weight(&self, address: ManagedAddress, weight_by_duration: bool) -> u64 {
+        let staked_nfts = self.get_staked_nfts(address);
+        if !weight_by_duration {
+            return self.get_staked_units(&staked_nfts);
+        }
+
+        let current_epoch = self.blockchain().get_block_epoch();
+        let mut weight = 0u64;
+        for nft in staked_nfts.iter() {
+            weight += (current_epoch - nft.stake_epoch + 1) * nft.amount;
+        }
+        weight
+    }
+
+    #[view(getProposal)]
+    fn get_proposal(&self, proposal_id: u64) -> Proposal<Self::Api> {
+        require!(!self.proposals(proposal_id).is_empty(), "proposal does not exist");
+        self.proposals(proposal_id).get()
+    }
+
+    #[view(getProposalResults)]
+    fn get_proposal_results(&self, proposal_id: u64) -> MultiValueEncoded<MultiValue2<ManagedBuffer, u64>> {
+        let proposal = self.get_proposal(proposal_id);
+        let mut results = MultiValueEncoded::new();
+        for (option_index, option) in proposal.options.iter().enumerate() {
+            let votes = self.proposal_votes(proposal_id, option_index).get();
+            results.push(((*option).clone(), votes).into());
+        }
+        results
+    }
+
+    #[event("sponsored_reward")]
+    fn sponsored_reward_event(
+        &self,
+        #[indexed] sponsor: &ManagedAddress,
+        #[indexed] token: &EgldOrEsdtTokenIdentifier,
+        #[indexed] epoch: u64,
+        #[indexed] fee: &BigUint,
+        amount: &BigUint,
+    );
+
+    #[event("integrator_unstake")]
+    fn integrator_unstake_event(
+        &self,
+        #[indexed] integrator: &ManagedAddress,
+        #[indexed] staker: &ManagedAddress,
+        #[indexed] receiver: &ManagedAddress,
+        epoch: u64,
+    );
+
+    #[event("reward_swapped")]
+    fn reward_swapped_event(
+        &self,
+        #[indexed] receiver: &ManagedAddress,
+        #[indexed] token_in: &TokenIdentifier,
+        #[indexed] amount_in: &BigUint,
+        #[indexed] token_out: &TokenIdentifier,
+        amount_out: &BigUint,
+    );
+
+    #[event("reward_swap_failed")]
+    fn reward_swap_failed_event(
+        &self,
+        #[indexed] receiver: &ManagedAddress,
+        #[indexed] token: &TokenIdentifier,
+        amount: &BigUint,
+    );
+
+    #[event("reward_redirected")]
+    fn reward_redirected_event(
+        &self,
+        #[indexed] staker: &ManagedAddress,
+        #[indexed] receiver: &ManagedAddress,
+        #[indexed] season_id: u32,
+        epoch: u64,
+    );
+
     #[view(getFullStakeInfo)]
-    #[storage_mapper("staked_nfts")]
+    #[storage_mapper("staked_units")]
     fn staked_nfts(&self) -> MapMapper<ManagedAddress, ManagedVec<StakedNftInfo>>;
 
+    // stakes made before SFT amounts were tracked, see migrateLegacyStakes
+    #[storage_mapper("staked_nfts")]
+    fn legacy_staked_nfts(&self) -> MapMapper<ManagedAddress, ManagedVec<LegacyStakedNftInfo>>;
+
+    // several addresses can hold units of the same SFT nonce
+    #[view(getNonceStakers)]
+    #[storage_mapper("nonce_stakers")]
+    fn nonce_stakers(&self, nonce: u64) -> UnorderedSetMapper<ManagedAddress>;
+
+    #[view(getStakedNonceAmount)]
+    #[storage_mapper("staked_nonce_amount")]
+    fn staked_nonce_amount(&self, address: &ManagedAddress, nonce: u64) -> SingleValueMapper<u64>;
+
+    #[view(getStakeCheckpoints)]
+    #[storage_mapper("stake_checkpoints")]
+    fn stake_checkpoints(&self, address: &ManagedAddress) -> VecMapper<StakeCheckpoint>;
+
+    #[storage_mapper("known_stakers")]
+    fn known_stakers(&self) -> UnorderedSetMapper<ManagedAddress>;
+
+    #[view(getLastRaffleId)]
+    #[storage_mapper("last_raffle_id")]
+    fn last_raffle_id(&self) -> SingleValueMapper<u64>;
+
+    #[view(getLastRaffleEpoch)]
+    #[storage_mapper("last_raffle_epoch")]
+    fn last_raffle_epoch(&self) -> SingleValueMapper<u64>;
+
+    #[storage_mapper("raffles")]
+    fn raffles(&self, raffle_id: u64) -> SingleValueMapper<RaffleInfo<Self::Api>>;
+
+    #[view(getPrizeInventory)]
+    #[storage_mapper("prize_inventory")]
+    fn prize_inventory(&self) -> VecMapper<EsdtTokenPayment>;
+
+    #[view(getLastPrizeDistributionEpoch)]
+    #[storage_mapper("last_prize_distribution_epoch")]
+    fn last_prize_distribution_epoch(&self) -> SingleValueMapper<u64>;
+
+    #[view(getReferralBonusPercentage)]
+    #[storage_mapper("referral_bonus_percentage")]
+    fn referral_bonus_percentage(&self) -> SingleValueMapper<u64>;
+
+    #[storage_mapper("referrer_of")]
+    fn referrer_of(&self, address: &ManagedAddress) -> SingleValueMapper<ManagedAddress>;
+
+    #[view(getReferrals)]
+    #[storage_mapper("referrals")]
+    fn referrals(&self, referrer: &ManagedAddress) -> UnorderedSetMapper<ManagedAddress>;
+
+    #[view(getReferralEarnings)]
+    #[storage_mapper("referral_earnings")]
+    fn referral_earnings(&self, referrer: &ManagedAddress, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<BigUint>;
+
+    #[view(getTotalStaked)]
+    #[storage_mapper("total_staked")]
+    fn total_staked(&self) -> SingleValueMapper<u64>;
+
+    #[storage_mapper("max_nfts_per_address")]
+    fn max_nfts_per_address(&self) -> SingleValueMapper<u64>;
+
+    #[storage_mapper("max_total_staked")]
+    fn max_total_staked(&self) -> SingleValueMapper<u64>;
+
+    #[storage_mapper("min_nfts_per_stake")]
+    fn min_nfts_per_stake(&self) -> SingleValueMapper<u64>;
+
+    #[view(getEligibleNonceRanges)]
+    #[storage_mapper("eligible_nonce_ranges")]
+    fn eligible_nonce_ranges(&self) -> VecMapper<NonceRange>;
+
+    #[view(getDeniedNonces)]
+    #[storage_mapper("denied_nonces")]
+    fn denied_nonces(&self) -> UnorderedSetMapper<u64>;
+
+    #[view(getDeniedAttributePrefixes)]
+    #[storage_mapper("denied_attribute_prefixes")]
+    fn denied_attribute_prefixes(&self) -> UnorderedSetMapper<ManagedBuffer>;
+
+    #[view(getStakingSetIds)]
+    #[storage_mapper("staking_set_ids")]
+    fn staking_set_ids(&self) -> UnorderedSetMapper<u32>;
+
+    #[storage_mapper("staking_sets")]
+    fn staking_sets(&self, set_id: u32) -> SingleValueMapper<StakingSet<Self::Api>>;
+
+    #[view(getNonceLockedUntil)]
+    #[storage_mapper("nonce_locked_until")]
+    fn nonce_locked_until(&self, address: &ManagedAddress, nonce: u64) -> SingleValueMapper<u64>;
+
+    #[view(getLastProposalId)]
+    #[storage_mapper("last_proposal_id")]
+    fn last_proposal_id(&self) -> SingleValueMapper<u64>;
+
+    #[storage_mapper("proposals")]
+    fn proposals(&self, proposal_id: u64) -> SingleValueMapper<Proposal<Self::Api>>;
+
+    #[storage_mapper("proposal_votes")]
+    fn proposal_votes(&self, proposal_id: u64, option_index: usize) -> SingleValueMapper<u64>;
+
+    #[storage_mapper("proposal_voters")]
+    fn proposal_voters(&self, proposal_id: u64) -> UnorderedSetMapper<ManagedAddress>;
+
     #[storage_mapper("origin_epoch")]
     fn origin_epoch(&self) -> SingleValueMapper<u64>;
 
@@ -226,11 +2052,97 @@ pub trait Adder {
     fn stake_token(&self) -> SingleValueMapper<TokenIdentifier>;
 
     #[storage_mapper("reward_payment_info")]
-    fn reward_payment_info(&self, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<RewardPaymentInfo<Self::Api>>;
+    fn default_reward_payment_info(&self, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<RewardPaymentInfo<Self::Api>>;
+
+    #[storage_mapper("season_reward_payment_info")]
+    fn season_reward_payment_info(&self, season_id: u32, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<RewardPaymentInfo<Self::Api>>;
+
+    #[view(getLastSeasonId)]
+    #[storage_mapper("last_season_id")]
+    fn last_season_id(&self) -> SingleValueMapper<u32>;
+
+    #[storage_mapper("seasons")]
+    fn seasons(&self, season_id: u32) -> SingleValueMapper<Season>;
+
+    #[storage_mapper("allowed_reward_tokens")]
+    fn allowed_reward_tokens(&self) -> UnorderedSetMapper<EgldOrEsdtTokenIdentifier>;
+
+    #[storage_mapper("min_reward_deposit")]
+    fn min_reward_deposit(&self, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<BigUint>;
+
+    #[view(getIntegrators)]
+    #[storage_mapper("integrators")]
+    fn integrators(&self) -> UnorderedSetMapper<ManagedAddress>;
+
+    #[storage_mapper("integrator_approvals")]
+    fn integrator_approvals(&self, staker: &ManagedAddress) -> UnorderedSetMapper<ManagedAddress>;
+
+    #[view(getSponsors)]
+    #[storage_mapper("sponsors")]
+    fn sponsors(&self) -> UnorderedSetMapper<ManagedAddress>;
+
+    #[view(getSponsorTokens)]
+    #[storage_mapper("sponsor_tokens")]
+    fn sponsor_tokens(&self) -> UnorderedSetMapper<EgldOrEsdtTokenIdentifier>;
+
+    #[view(getSponsorFeePercentage)]
+    #[storage_mapper("sponsor_fee_percentage")]
+    fn sponsor_fee_percentage(&self) -> SingleValueMapper<u64>;
+
+    #[view(getRewardSchedule)]
+    #[storage_mapper("reward_schedule")]
+    fn reward_schedule(&self, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<RewardSchedule<Self::Api>>;
+
+    #[storage_mapper("reward_deposit_history")]
+    fn reward_deposit_history(&self) -> VecMapper<RewardDepositRecord<Self::Api>>;
+
+    #[storage_mapper("recent_reward_deposits")]
+    fn recent_reward_deposits(&self, season_id: u32, reward_token: &EgldOrEsdtTokenIdentifier) -> VecMapper<RecentRewardDeposit<Self::Api>>;
+
+    #[storage_mapper("recent_reward_deposits_oldest")]
+    fn recent_reward_deposits_oldest(&self, season_id: u32, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<usize>;
+
+    #[view(getSeasonRewardTokens)]
+    #[storage_mapper("reward_tokens")]
+    fn reward_tokens(&self, season_id: u32) -> UnorderedSetMapper<EgldOrEsdtTokenIdentifier>;
+
+    #[storage_mapper("reward_destination")]
+    fn reward_destination(&self, address: &ManagedAddress) -> SingleValueMapper<ManagedAddress>;
+
+    #[storage_mapper("preferred_reward_token")]
+    fn preferred_reward_token(&self, address: &ManagedAddress) -> SingleValueMapper<TokenIdentifier>;
+
+    #[view(getSwapPair)]
+    #[storage_mapper("swap_pair")]
+    fn swap_pair(&self, token_in: &TokenIdentifier, token_out: &TokenIdentifier) -> SingleValueMapper<ManagedAddress>;
+
+    #[storage_mapper("owed_reward_tokens")]
+    fn owed_reward_tokens(&self) -> UnorderedSetMapper<EgldOrEsdtTokenIdentifier>;
+
+    #[storage_mapper("total_owed")]
+    fn total_owed(&self, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<BigUint>;
+
+    #[view(isTotalOwedSeedingClosed)]
+    #[storage_mapper("total_owed_seeding_closed")]
+    fn total_owed_seeding_closed(&self) -> SingleValueMapper<bool>;
+
+    #[storage_mapper("owed_seeded_addresses")]
+    fn owed_seeded_addresses(&self) -> UnorderedSetMapper<ManagedAddress>;
+
+    #[storage_mapper("lifetime_claimed")]
+    fn lifetime_claimed(&self, address: &ManagedAddress, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<BigUint>;
+
+    #[storage_mapper("claimed_reward_tokens")]
+    fn claimed_reward_tokens(&self, address: &ManagedAddress) -> UnorderedSetMapper<EgldOrEsdtTokenIdentifier>;
+
+    #[storage_mapper("total_claimed")]
+    fn total_claimed(&self, reward_token: &EgldOrEsdtTokenIdentifier) -> SingleValueMapper<BigUint>;
 
-    #[view(getClaimableRewards)]
     #[storage_mapper("claimable_rewards")]
-    fn claimable_rewards(&self, address: &ManagedAddress) -> VecMapper<EgldOrEsdtTokenPayment>;
+    fn default_claimable_rewards(&self, address: &ManagedAddress) -> VecMapper<EgldOrEsdtTokenPayment>;
+
+    #[storage_mapper("season_claimable_rewards")]
+    fn season_claimable_rewards(&self, season_id: u32, address: &ManagedAddress) -> VecMapper<EgldOrEsdtTokenPayment>;
 }
 
 #[derive(ManagedVecItem, NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
@@ -241,8 +2153,99 @@ pub struct RewardPaymentInfo<M: ManagedTypeApi> {
     pub last_reward_per_epoch_per_nonce: BigUint<M>,
 }
 
+#[derive(ManagedVecItem, NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
+pub struct RewardAmount<M: ManagedTypeApi> {
+    pub token_identifier: EgldOrEsdtTokenIdentifier<M>,
+    pub amount: BigUint<M>,
+}
+
+#[derive(NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi)]
+pub struct RewardSchedule<M: ManagedTypeApi> {
+    pub amount_per_period: BigUint<M>,
+    pub period_epochs: u64,
+    pub caller_tip: BigUint<M>,
+    pub budget: BigUint<M>,
+    pub next_distribution_epoch: u64,
+}
+
+#[derive(ManagedVecItem, NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
+pub struct RewardDepositRecord<M: ManagedTypeApi> {
+    pub season_id: u32,
+    pub depositor: ManagedAddress<M>,
+    pub epoch: u64,
+    pub from_epoch: u64,
+    pub token_identifier: EgldOrEsdtTokenIdentifier<M>,
+    pub token_nonce: u64,
+    pub amount: BigUint<M>,
+    pub total_tickets: u64,
+    pub reward_per_ticket: BigUint<M>,
+    pub staker_count: usize,
+}
+
+#[derive(ManagedVecItem, NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
+pub struct RecentRewardDeposit<M: ManagedTypeApi> {
+    pub epoch: u64,
+    pub amount: BigUint<M>,
+    pub tickets: u64,
+}
+
 #[derive(ManagedVecItem, NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
 pub struct StakedNftInfo {
     pub nonce: u64,
-    pub stake_epoch: u64
-}
\ No newline at end of file
+    pub stake_epoch: u64,
+    pub amount: u64,
+}
+
+#[derive(ManagedVecItem, NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
+pub struct LegacyStakedNftInfo {
+    pub nonce: u64,
+    pub stake_epoch: u64,
+}
+
+#[derive(NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
+pub struct Season {
+    pub start_epoch: u64,
+    pub end_epoch: u64,
+}
+
+#[derive(ManagedVecItem, NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
+pub struct NonceRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+#[derive(ManagedVecItem, NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, PartialEq)]
+pub struct StakeCheckpoint {
+    pub epoch: u64,
+    pub staked_count: u64,
+    pub set_bonus_weight: u64,
+}
+
+#[derive(NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi)]
+pub struct Proposal<M: ManagedTypeApi> {
+    pub description: ManagedBuffer<M>,
+    pub options: ManagedVec<M, ManagedBuffer<M>>,
+    pub end_epoch: u64,
+    pub weight_by_duration: bool,
+}
+
+#[derive(NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi)]
+pub struct RaffleInfo<M: ManagedTypeApi> {
+    pub epoch: u64,
+    pub prize: EgldOrEsdtTokenPayment<M>,
+    // paid to the first winner on top of the prize
+    pub remainder: BigUint<M>,
+    pub winners: ManagedVec<M, ManagedAddress<M>>,
+}
+
+#[derive(NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi)]
+pub struct StakingSet<M: ManagedTypeApi> {
+    pub nonces: ManagedVec<M, u64>,
+    pub bonus_percentage: u64,
+}
+
+#[derive(NestedEncode, NestedDecode, TopEncode, TopDecode, TypeAbi, Clone, Copy, PartialEq)]
+pub enum PrizeDistributionMode {
+    TopHolders,
+    WeightedRandom,
+}
@@ -0,0 +1,9 @@
+elrond_wasm::imports!();
+
+// the subset of the DEX pair contract used to swap rewards
+#[elrond_wasm::proxy]
+pub trait DexPair {
+    #[payable("*")]
+    #[endpoint(swapTokensFixedInput)]
+    fn swap_tokens_fixed_input(&self, token_out: TokenIdentifier, amount_out_min: BigUint) -> EsdtTokenPayment;
+}
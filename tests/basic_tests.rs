@@ -1,18 +1,98 @@
 use cnuns_staking::*;
+use elrond_wasm::contract_base::ContractBase;
 use elrond_wasm::elrond_codec::multi_types::OptionalValue;
-use elrond_wasm::types::{Address, BoxedBytes, ManagedVec, ManagedBuffer};
+use elrond_wasm::storage::{mappers::{MapMapper, SingleValueMapper, StorageMapper, VecMapper}, StorageKey};
+use elrond_wasm::types::{
+    Address, BoxedBytes, EgldOrEsdtTokenIdentifier, EgldOrEsdtTokenPayment, EsdtTokenPayment, ManagedAddress, ManagedAsyncCallResult,
+    ManagedBuffer, ManagedVec, MultiValueEncoded,
+};
 use elrond_wasm_debug::{
-    managed_biguint, rust_biguint, testing_framework::*, num_bigint, managed_token_id, managed_address,
+    managed_biguint, managed_buffer, rust_biguint, testing_framework::*, num_bigint, managed_token_id, managed_address,
     DebugApi,
 };
-use elrond_wasm_debug::tx_mock::TxInputESDT;
+use elrond_wasm_debug::tx_mock::{TxInputESDT, TxResult};
 
-const WASM_PATH: &'static str = "../output/cnuns_staking.wasm";
+const WASM_PATH: &str = "../output/cnuns_staking.wasm";
 const STAKE_TOKEN: &[u8] = b"CNUN-123456";
 const INVALID_STAKE_TOKEN: &[u8] = b"RANDOM-123456";
 const REWARD_TOKEN: &[u8] = b"REW-abcdef";
+const SECOND_REWARD_TOKEN: &[u8] = b"REWTWO-abcdef";
+const PRIZE_TOKEN: &[u8] = b"PRIZE-abcdef";
 const REWARD_AMOUNT: u64 = 1_000_000_000;
 
+const MARKETPLACE_WASM_PATH: &str = "../output/marketplace_mock.wasm";
+
+// minimal marketplace selling staked NFTs through the integrator interface
+mod marketplace_mock {
+    elrond_wasm::imports!();
+
+    use cnuns_staking::ProxyTrait as _;
+
+    #[elrond_wasm::contract]
+    pub trait MarketplaceMock {
+        #[init]
+        fn init(&self, staking_address: ManagedAddress) {
+            self.staking_address().set(&staking_address);
+        }
+
+        #[payable("EGLD")]
+        #[endpoint(buyStakedNft)]
+        fn buy_staked_nft(&self, seller: ManagedAddress, nonce: u64) {
+            let price = self.call_value().egld_value();
+            let staking_address = self.staking_address().get();
+            let (staked_amount, _) = self.staking_proxy(staking_address.clone())
+                .get_stake_status(seller.clone(), nonce)
+                .execute_on_dest_context::<MultiValue2<u64, u64>>()
+                .into_tuple();
+            require!(staked_amount > 0, "NFT is not staked");
+
+            let buyer = self.blockchain().get_caller();
+            let mut nonces = MultiValueEncoded::new();
+            nonces.push((nonce, 1u64).into());
+            self.staking_proxy(staking_address)
+                .integrator_unstake(seller.clone(), buyer, nonces)
+                .execute_on_dest_context::<()>();
+            self.send().direct_egld(&seller, &price);
+        }
+
+        #[proxy]
+        fn staking_proxy(&self, sc_address: ManagedAddress) -> cnuns_staking::Proxy<Self::Api>;
+
+        #[storage_mapper("staking_address")]
+        fn staking_address(&self) -> SingleValueMapper<ManagedAddress>;
+    }
+}
+
+const DEX_PAIR_WASM_PATH: &str = "../output/dex_pair_mock.wasm";
+
+// DEX pair with a fixed exchange rate, paying out of its own balance
+mod dex_pair_mock {
+    elrond_wasm::imports!();
+
+    #[elrond_wasm::contract]
+    pub trait DexPairMock {
+        #[init]
+        fn init(&self, rate: u64) {
+            self.rate().set(rate);
+        }
+
+        #[payable("*")]
+        #[endpoint(swapTokensFixedInput)]
+        fn swap_tokens_fixed_input(&self, token_out: TokenIdentifier, amount_out_min: BigUint) -> EsdtTokenPayment {
+            let (_, amount_in) = self.call_value().single_fungible_esdt();
+            let amount_out = amount_in * self.rate().get();
+            require!(amount_out >= amount_out_min, "Slippage exceeded");
+
+            let caller = self.blockchain().get_caller();
+            self.send().direct_esdt(&caller, &token_out, 0, &amount_out);
+            EsdtTokenPayment::new(token_out, 0, amount_out)
+        }
+
+        #[storage_mapper("rate")]
+        fn rate(&self) -> SingleValueMapper<u64>;
+    }
+}
+
 struct StakingSetup<StakingObjBuilder>
 where
     StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
@@ -58,9 +138,18 @@ where
         })
         .assert_ok();
 
+    blockchain_wrapper
+        .execute_tx(&owner_address, &staking_sc_wrapper, &rust_zero, |sc| {
+            sc.allow_reward_token(EgldOrEsdtTokenIdentifier::egld(), managed_biguint!(0));
+            sc.allow_reward_token(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)), managed_biguint!(0));
+            sc.allow_reward_token(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(SECOND_REWARD_TOKEN)), managed_biguint!(0));
+        })
+        .assert_ok();
+
     let reward_amount = num_bigint::ToBigUint::to_biguint(&(10 * REWARD_AMOUNT)).unwrap();
     blockchain_wrapper.set_egld_balance(&owner_address, &reward_amount);
     blockchain_wrapper.set_esdt_balance(&owner_address, REWARD_TOKEN, &reward_amount);
+    blockchain_wrapper.set_esdt_balance(&owner_address, SECOND_REWARD_TOKEN, &reward_amount);
     
     let nft_balance = num_bigint::ToBigUint::to_biguint(&1).unwrap();
     blockchain_wrapper.set_nft_balance(&client_address, INVALID_STAKE_TOKEN, 1, &nft_balance, &BoxedBytes::empty());
@@ -115,7 +204,7 @@ fn test_invalid_stake_attempts() {
     // only stake an invalid NFT
     setup.blockchain_wrapper
         .execute_esdt_multi_transfer(&setup.client_address, &setup.staking_sc_wrapper, &vec, |sc| {
-            sc.stake();
+            sc.stake(OptionalValue::None);
         })
         .assert_user_error("one or more NFTs is not eligible for staking");
 
@@ -131,14 +220,14 @@ fn test_invalid_stake_attempts() {
     // stake invalid NFT combined with other NFTs
     setup.blockchain_wrapper
         .execute_esdt_multi_transfer(&setup.client_address, &setup.staking_sc_wrapper, &vec, |sc| {
-            sc.stake();
+            sc.stake(OptionalValue::None);
         })
         .assert_user_error("one or more NFTs is not eligible for staking");
 
     // stake nothing
     setup.blockchain_wrapper
         .execute_esdt_multi_transfer(&setup.client_address, &setup.staking_sc_wrapper, &Vec::new(), |sc| {
-            sc.stake();
+            sc.stake(OptionalValue::None);
         })
         .assert_user_error("cannot stake nothing");
 }
@@ -166,6 +255,167 @@ fn test_stake_unstake_attempts() {
 }
 
 
+#[test]
+fn test_nonce_stakers_index() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    check_nonce_stakers(&mut sc_setup, 1, &[&caller1]);
+    check_nonce_stakers(&mut sc_setup, 2, &[&caller1]);
+    check_nonce_stakers(&mut sc_setup, 6, &[&caller2]);
+    check_nonce_stakers(&mut sc_setup, 3, &[]);
+
+    // cannot unstake someone else's nonce, nor a nonce that was never staked
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller1, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut nonces = MultiValueEncoded::new();
+            nonces.push((6u64, 1u64).into());
+            sc.unstake(nonces);
+        })
+        .assert_user_error("nonce 6 is not staked by caller");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller1, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut nonces = MultiValueEncoded::new();
+            nonces.push((3u64, 1u64).into());
+            sc.unstake(nonces);
+        })
+        .assert_user_error("nonce 3 is not staked by caller");
+
+    unstake_nfts(&mut sc_setup, &caller1, &[1]);
+    check_nonce_stakers(&mut sc_setup, 1, &[]);
+    check_nonce_stakers(&mut sc_setup, 2, &[&caller1]);
+
+    // end staking releases every remaining nonce
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 20);
+    trigger_end_stake(&mut sc_setup);
+    check_nonce_stakers(&mut sc_setup, 2, &[]);
+    check_nonce_stakers(&mut sc_setup, 6, &[]);
+}
+
+#[test]
+fn test_sft_staking() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+    sc_setup.blockchain_wrapper.set_nft_balance(&caller1, STAKE_TOKEN, 20, &rust_biguint!(5), &BoxedBytes::empty());
+    sc_setup.blockchain_wrapper.set_nft_balance(&caller2, STAKE_TOKEN, 20, &rust_biguint!(3), &BoxedBytes::empty());
+    sc_setup.blockchain_wrapper.set_esdt_balance(&caller3, STAKE_TOKEN, &rust_biguint!(10));
+
+    try_stake_sfts(&mut sc_setup, &caller1, &[(20, 5)]).assert_ok();
+    try_stake_sfts(&mut sc_setup, &caller2, &[(20, 3), (6, 1)]).assert_ok();
+    try_stake_sfts(&mut sc_setup, &caller3, &[(0, 10)]).assert_user_error("fungible tokens cannot be staked");
+    check_invariants(&mut sc_setup);
+    check_nonce_stakers(&mut sc_setup, 20, &[&caller1, &caller2]);
+
+    // tickets scale with the staked amount
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    check_total_payable_epochs(&mut sc_setup, &caller1, 0, 10, 50);
+    check_total_payable_epochs(&mut sc_setup, &caller2, 0, 10, 40);
+
+    try_unstake_sfts(&mut sc_setup, &caller1, &[(20, 0)]).assert_user_error("cannot unstake zero units of nonce 20");
+    try_unstake_sfts(&mut sc_setup, &caller1, &[(20, 6)]).assert_user_error("not enough units of nonce 20 staked");
+    try_unstake_sfts(&mut sc_setup, &caller1, &[(20, 2), (20, 4)]).assert_user_error("not enough units of nonce 20 staked");
+
+    try_unstake_sfts(&mut sc_setup, &caller1, &[(20, 2)]).assert_ok();
+    check_invariants(&mut sc_setup);
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, STAKE_TOKEN, 20, &rust_biguint!(2), Option::None);
+    check_total_payable_epochs(&mut sc_setup, &caller1, 0, 10, 30);
+    check_nonce_stakers(&mut sc_setup, 20, &[&caller1, &caller2]);
+
+    try_unstake_sfts(&mut sc_setup, &caller1, &[(20, 3)]).assert_ok();
+    check_invariants(&mut sc_setup);
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, STAKE_TOKEN, 20, &rust_biguint!(5), Option::None);
+    check_nonce_stakers(&mut sc_setup, 20, &[&caller2]);
+
+    // end staking returns every staked unit
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 40);
+    trigger_end_stake(&mut sc_setup);
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller2, STAKE_TOKEN, 20, &rust_biguint!(3), Option::None);
+    check_nonce_stakers(&mut sc_setup, 20, &[]);
+}
+
+#[test]
+fn test_legacy_stake_migration() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let owner = sc_setup.owner_address.clone();
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+
+    let sc_address = sc_setup.staking_sc_wrapper.address_ref().clone();
+    for nonce in [21u64, 22, 26, 31] {
+        sc_setup.blockchain_wrapper.set_nft_balance(&sc_address, STAKE_TOKEN, nonce, &rust_biguint!(1), &BoxedBytes::empty());
+    }
+    write_legacy_stake(&mut sc_setup, &caller1, &[(21, 0), (22, 3)]);
+    write_legacy_stake(&mut sc_setup, &caller2, &[(26, 2)]);
+    write_legacy_stake(&mut sc_setup, &caller3, &[(31, 1)]);
+
+    // a legacy stake is migrated on the spot when its staker unstakes
+    unstake_nfts(&mut sc_setup, &caller3, &[31]);
+
+    // rewards cannot skip the stakers that are not migrated yet
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(REWARD_AMOUNT), |sc| {
+            sc.deposit_reward();
+        })
+        .assert_user_error("legacy stakes must be migrated first");
+
+    migrate_legacy_stakes(&mut sc_setup, 1, 1);
+    migrate_legacy_stakes(&mut sc_setup, 5, 0);
+    check_nonce_stakers(&mut sc_setup, 21, &[&caller1]);
+    check_nonce_stakers(&mut sc_setup, 22, &[&caller1]);
+    check_nonce_stakers(&mut sc_setup, 26, &[&caller2]);
+    check_invariants(&mut sc_setup);
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let staked_nfts: Vec<_> = sc.get_staked_nfts(managed_address!(&caller1))
+                .iter()
+                .map(|nft| (nft.nonce, nft.stake_epoch, nft.amount))
+                .collect();
+            assert_eq!(vec![(21, 0, 1), (22, 3, 1)], staked_nfts);
+            assert_eq!(17, sc.get_historical_eligible_tickets(managed_address!(&caller1), 0, 10));
+            assert_eq!(8, sc.get_historical_eligible_tickets(managed_address!(&caller2), 0, 10));
+        })
+        .assert_ok();
+
+    // c1 = 10 + 7, c2 = 8
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(REWARD_AMOUNT), |sc| {
+            assert_eq!(managed_biguint!(REWARD_AMOUNT / 25), sc.deposit_reward());
+        })
+        .assert_ok();
+    check_invariants(&mut sc_setup);
+
+    // the backfilled index knows who staked each migrated nonce
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller2, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut nonces = MultiValueEncoded::new();
+            nonces.push((21u64, 1u64).into());
+            sc.unstake(nonces);
+        })
+        .assert_user_error("nonce 21 is not staked by caller");
+
+    unstake_nfts(&mut sc_setup, &caller1, &[21]);
+    trigger_end_stake(&mut sc_setup);
+    sc_setup.blockchain_wrapper.check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, STAKE_TOKEN, 22, &rust_biguint!(1), Option::None);
+    sc_setup.blockchain_wrapper.check_nft_balance::<ManagedBuffer<DebugApi>>(&caller2, STAKE_TOKEN, 26, &rust_biguint!(1), Option::None);
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller1, &rust_biguint!(REWARD_AMOUNT / 25 * 17));
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller2, &rust_biguint!(REWARD_AMOUNT / 25 * 8));
+}
+
 #[test]
 fn test_simple_split_rewards() {
     let _ = DebugApi::dummy();
@@ -306,190 +556,2123 @@ fn test_complex_split_rewards() {
 }
 
 #[test]
-fn test_end_stake() {
+fn test_preview_reward() {
     let _ = DebugApi::dummy();
     let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
 
     let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
     stake_nfts(&mut sc_setup, &caller1, &[1]);
-    
-    // distribute EGLD rewards in epoch 10
-    let mut block_epoch = 10u64;
-    sc_setup.blockchain_wrapper.set_block_epoch(block_epoch);
-
-    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / block_epoch);
 
-    // distribute ESDT rewards in epoch 20
-    block_epoch = 20u64;
-    sc_setup.blockchain_wrapper.set_block_epoch(block_epoch);
+    sc_setup.blockchain_wrapper.set_block_epoch(5u64);
+    stake_nfts(&mut sc_setup, &caller2, &[6, 7, 8]);
 
-    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / block_epoch);
-    trigger_end_stake(&mut sc_setup);
+    // c1: 10 epochs * 1 unit = 10, c2: 5 epochs * 3 units = 15
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    let expected_unit_reward = REWARD_AMOUNT / 25;
+    check_preview_reward(&mut sc_setup, &caller1, b"EGLD", REWARD_AMOUNT, expected_unit_reward * 10);
+    check_preview_reward(&mut sc_setup, &caller2, b"EGLD", REWARD_AMOUNT, expected_unit_reward * 15);
+    check_preview_reward(&mut sc_setup, &caller3, b"EGLD", REWARD_AMOUNT, 0);
+    check_ticket_share(&mut sc_setup, &caller1, b"EGLD", 10, 25);
+    check_ticket_share(&mut sc_setup, &caller2, b"EGLD", 15, 25);
 
-    sc_setup.blockchain_wrapper
-            .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, &STAKE_TOKEN, 1, &rust_biguint!(1), Option::None);
+    deposit_egld_rewards(&mut sc_setup, expected_unit_reward);
 
-    sc_setup.blockchain_wrapper
-        .check_esdt_balance(&caller1, REWARD_TOKEN, &rust_biguint!(REWARD_AMOUNT));
-    sc_setup.blockchain_wrapper
-        .check_egld_balance(&caller1, &rust_biguint!(REWARD_AMOUNT));
+    // EGLD was just paid, the ESDT reward was never paid
+    check_preview_reward(&mut sc_setup, &caller1, b"EGLD", REWARD_AMOUNT, 0);
+    check_ticket_share(&mut sc_setup, &caller1, b"EGLD", 0, 0);
+    check_preview_reward(&mut sc_setup, &caller1, REWARD_TOKEN, REWARD_AMOUNT, expected_unit_reward * 10);
 
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, expected_unit_reward * 10, 0);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, expected_unit_reward * 15, 0);
 }
 
-/* Helper functions */
-fn check_origin_epoch<StakingObjBuilder>(
-    setup: &mut StakingSetup<StakingObjBuilder>,
-    expected: u64,
-) where
-    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
-{
-    setup.blockchain_wrapper
-        .execute_query(&setup.staking_sc_wrapper, |sc| {
-            let origin_epoch = sc.get_origin_epoch();
-            assert_eq!(expected, origin_epoch);
-        })
-        .assert_ok();
-}
+#[test]
+fn test_reward_per_nft_per_epoch_history() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
 
-fn check_stake_token<StakingObjBuilder>(
-    setup: &mut StakingSetup<StakingObjBuilder>,
-) where
-    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
-{
-    setup.blockchain_wrapper
-        .execute_query(&setup.staking_sc_wrapper, |sc| {
-            let stake_token = sc.get_stake_token();
-            let expected = managed_token_id!(STAKE_TOKEN);
-            assert_eq!(expected, stake_token);
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    // one deposit per epoch, 2 tickets each time
+    for epoch in 1..=32u64 {
+        sc_setup.blockchain_wrapper.set_block_epoch(epoch);
+        sc_setup.blockchain_wrapper
+            .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(epoch * 200), |sc| {
+                let reward_per_epoch_per_nonce = sc.deposit_reward();
+                assert_eq!(managed_biguint!(epoch * 100), reward_per_epoch_per_nonce);
+            })
+            .assert_ok();
+    }
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            // only the most recent deposits are kept, oldest first
+            let recent_deposits = sc.get_recent_reward_deposits(EgldOrEsdtTokenIdentifier::egld());
+            assert_eq!(30, recent_deposits.len());
+            for (i, deposit) in recent_deposits.iter().enumerate() {
+                let epoch = i as u64 + 3;
+                assert_eq!(epoch, deposit.epoch);
+                assert_eq!(managed_biguint!(epoch * 200), deposit.amount);
+                assert_eq!(2, deposit.tickets);
+            }
+
+            assert_eq!(managed_biguint!(3_200), sc.get_last_reward_per_nft_per_epoch(EgldOrEsdtTokenIdentifier::egld()));
+            assert_eq!(managed_biguint!(3_150), sc.get_average_reward_per_nft_per_epoch(EgldOrEsdtTokenIdentifier::egld(), 2));
+            // (300 + ... + 3_200) / 30
+            assert_eq!(managed_biguint!(1_750), sc.get_average_reward_per_nft_per_epoch(EgldOrEsdtTokenIdentifier::egld(), 100));
+            assert_eq!(managed_biguint!(0), sc.get_average_reward_per_nft_per_epoch(EgldOrEsdtTokenIdentifier::egld(), 0));
+
+            let reward_token = EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN));
+            assert_eq!(managed_biguint!(0), sc.get_average_reward_per_nft_per_epoch(reward_token, 5));
         })
         .assert_ok();
 }
 
-fn stake_nfts<StakingObjBuilder>(
-    setup: &mut StakingSetup<StakingObjBuilder>,
-    caller: &Address,
-    nonces: &[u64],
-) where
-    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
-{
-    let nft_balance = num_bigint::ToBigUint::to_biguint(&1).unwrap();
+#[test]
+fn test_reward_deposit_history() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let owner = sc_setup.owner_address.clone();
 
-    let mut vec = Vec::new();
-    for nonce in nonces.iter() {
-        let tx_input = TxInputESDT {
-            token_identifier: Vec::from(STAKE_TOKEN),
-            nonce: *nonce,
-            value: nft_balance.clone()
-        };
-        vec.push(tx_input);
-    }
-    
-    setup.blockchain_wrapper
-        .execute_esdt_multi_transfer(caller, &setup.staking_sc_wrapper, &vec, |sc| {
-            sc.stake();
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(5u64);
+    stake_nfts(&mut sc_setup, &caller2, &[6, 7, 8]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 25);
+
+    // c3 has no tickets yet and is not credited
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    let caller3 = sc_setup.client3_address.clone();
+    stake_nfts(&mut sc_setup, &caller3, &[11]);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 65);
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(2, sc.get_reward_deposit_history_length());
+
+            let records = sc.get_reward_deposit_history(0, 10).to_vec();
+            assert_eq!(2, records.len());
+
+            let egld_record = records.get(0);
+            assert_eq!(managed_address!(&owner), egld_record.depositor);
+            assert_eq!(10, egld_record.epoch);
+            assert_eq!(0, egld_record.from_epoch);
+            assert_eq!(EgldOrEsdtTokenIdentifier::egld(), egld_record.token_identifier);
+            assert_eq!(0, egld_record.token_nonce);
+            assert_eq!(managed_biguint!(REWARD_AMOUNT), egld_record.amount);
+            assert_eq!(25, egld_record.total_tickets);
+            assert_eq!(managed_biguint!(REWARD_AMOUNT / 25), egld_record.reward_per_ticket);
+            assert_eq!(2, egld_record.staker_count);
+
+            // ESDT rewards were never paid before, so they cover epochs 0 to 20
+            let esdt_record = records.get(1);
+            assert_eq!(20, esdt_record.epoch);
+            assert_eq!(0, esdt_record.from_epoch);
+            assert_eq!(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)), esdt_record.token_identifier);
+            assert_eq!(65, esdt_record.total_tickets);
+            assert_eq!(managed_biguint!(REWARD_AMOUNT / 65), esdt_record.reward_per_ticket);
+            assert_eq!(2, esdt_record.staker_count);
+
+            let second_page = sc.get_reward_deposit_history(1, 1).to_vec();
+            assert_eq!(1, second_page.len());
+            assert_eq!(20, second_page.get(0).epoch);
+
+            assert_eq!(0, sc.get_reward_deposit_history(2, 10).to_vec().len());
         })
         .assert_ok();
 }
 
-fn unstake_nfts<StakingObjBuilder>(
-    setup: &mut StakingSetup<StakingObjBuilder>,
-    caller: &Address,
-    nonces: &[u64],
-) where
-    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
-{
-    let nft_balance = num_bigint::ToBigUint::to_biguint(&1).unwrap();
-    let zero = num_bigint::ToBigUint::to_biguint(&0).unwrap();
-    let mut unstake_params = ManagedVec::new();
-    // let mut vec = Vec::new();
-    for nonce in nonces.iter() {
-        unstake_params.push(*nonce);
-    }
-    
-    setup.blockchain_wrapper
-        .execute_tx(caller, &setup.staking_sc_wrapper, &zero, |sc| {
-            sc.unstake(unstake_params);
+#[test]
+fn test_all_reward_payment_info() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let caller1 = sc_setup.client_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(0, sc.get_all_reward_payment_info().into_iter().count());
+            let unknown_info = sc.get_reward_payment_info(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(INVALID_STAKE_TOKEN)));
+            assert_eq!(0, unknown_info.last_paid_epoch);
+            assert_eq!(managed_biguint!(0), unknown_info.total_paid_so_far);
         })
         .assert_ok();
 
-    for nonce in nonces.iter() {
-        setup.blockchain_wrapper
-            .check_nft_balance::<ManagedBuffer<DebugApi>>(caller, &STAKE_TOKEN, *nonce, &nft_balance, Option::None);
-    }
-}
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 20);
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 40);
 
-fn deposit_egld_rewards<StakingObjBuilder>(
-    setup: &mut StakingSetup<StakingObjBuilder>,
-    expected_reward_per_unit: u64,
-) where
-    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
-{
-    let reward_amount = num_bigint::ToBigUint::to_biguint(&REWARD_AMOUNT).unwrap();
-    
-    setup.blockchain_wrapper
-        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &reward_amount, |sc| {
-            let reward_per_epoch_per_nonce = sc.deposit_reward();
-            assert_eq!(managed_biguint!(expected_reward_per_unit), reward_per_epoch_per_nonce);
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let all_info: Vec<_> = sc.get_all_reward_payment_info().into_iter().map(|info| info.into_tuple()).collect();
+            assert_eq!(2, all_info.len());
+            for (token, info) in all_info.iter() {
+                if *token == EgldOrEsdtTokenIdentifier::egld() {
+                    assert_eq!(10, info.last_paid_epoch);
+                    assert_eq!(managed_biguint!(REWARD_AMOUNT / 20), info.last_reward_per_epoch_per_nonce);
+                } else {
+                    assert_eq!(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)), *token);
+                    assert_eq!(20, info.last_paid_epoch);
+                    assert_eq!(managed_biguint!(REWARD_AMOUNT / 40), info.last_reward_per_epoch_per_nonce);
+                }
+                assert_eq!(managed_biguint!(REWARD_AMOUNT), info.total_paid_so_far);
+            }
         })
         .assert_ok();
 }
 
-fn deposit_esdt_rewards<StakingObjBuilder>(
-    setup: &mut StakingSetup<StakingObjBuilder>,
-    expected_reward_per_unit: u64,
-) where
-    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
-{
-    let reward_amount = num_bigint::ToBigUint::to_biguint(&REWARD_AMOUNT).unwrap();
-    
-    setup.blockchain_wrapper
-        .execute_esdt_transfer(&setup.owner_address, &setup.staking_sc_wrapper, &REWARD_TOKEN, 0u64, &reward_amount, |sc| {
-            let reward_per_epoch_per_nonce = sc.deposit_reward();
-            assert_eq!(managed_biguint!(expected_reward_per_unit), reward_per_epoch_per_nonce);
+#[test]
+fn test_multi_token_reward_deposit() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let owner = sc_setup.owner_address.clone();
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_esdt_rewards(&mut sc_setup, 33_333_333);
+
+    let duplicate_rewards = vec![
+        TxInputESDT { token_identifier: Vec::from(REWARD_TOKEN), nonce: 0, value: rust_biguint!(1_000) },
+        TxInputESDT { token_identifier: Vec::from(REWARD_TOKEN), nonce: 0, value: rust_biguint!(1_000) },
+    ];
+    sc_setup.blockchain_wrapper
+        .execute_esdt_multi_transfer(&owner, &sc_setup.staking_sc_wrapper, &duplicate_rewards, |sc| {
+            sc.deposit_rewards();
+        })
+        .assert_user_error("duplicate reward token");
+
+    // REW was last paid at epoch 10 (30 tickets since), REWTWO never (60 tickets since the start)
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    let rewards = vec![
+        TxInputESDT { token_identifier: Vec::from(REWARD_TOKEN), nonce: 0, value: rust_biguint!(3_000) },
+        TxInputESDT { token_identifier: Vec::from(SECOND_REWARD_TOKEN), nonce: 0, value: rust_biguint!(600) },
+    ];
+    sc_setup.blockchain_wrapper
+        .execute_esdt_multi_transfer(&owner, &sc_setup.staking_sc_wrapper, &rewards, |sc| {
+            let rewards_per_ticket: Vec<_> = sc.deposit_rewards().into_iter().map(|reward| reward.into_tuple()).collect();
+            assert_eq!(2, rewards_per_ticket.len());
+            assert_eq!(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)), rewards_per_ticket[0].0);
+            assert_eq!(managed_biguint!(100), rewards_per_ticket[0].1);
+            assert_eq!(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(SECOND_REWARD_TOKEN)), rewards_per_ticket[1].0);
+            assert_eq!(managed_biguint!(10), rewards_per_ticket[1].1);
         })
         .assert_ok();
-}
+    check_invariants(&mut sc_setup);
 
-fn trigger_end_stake<StakingObjBuilder>(
-    setup: &mut StakingSetup<StakingObjBuilder>,
-) where
-    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
-{
-    let reward_amount = num_bigint::ToBigUint::to_biguint(&REWARD_AMOUNT).unwrap();
-    
-    setup.blockchain_wrapper
-        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
-            sc.end_staking();
+    // EGLD is deposited on its own, it was never paid before so 90 tickets share it
+    sc_setup.blockchain_wrapper.set_block_epoch(30u64);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.deposit_rewards();
+        })
+        .assert_user_error("No payment sent");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(900), |sc| {
+            let rewards_per_ticket: Vec<_> = sc.deposit_rewards().into_iter().map(|reward| reward.into_tuple()).collect();
+            assert_eq!(vec![(EgldOrEsdtTokenIdentifier::egld(), managed_biguint!(10))], rewards_per_ticket);
         })
         .assert_ok();
+    check_invariants(&mut sc_setup);
+
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, 600, 20 * 33_333_333 + 20 * 100);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, 300, 10 * 33_333_333 + 10 * 100);
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, SECOND_REWARD_TOKEN, &rust_biguint!(400));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller2, SECOND_REWARD_TOKEN, &rust_biguint!(200));
 }
 
-fn claim_rewards_and_check_balance<StakingObjBuilder>(
-    setup: &mut StakingSetup<StakingObjBuilder>,
-    caller: &Address,
-    expected_total_egld_reward: u64,
-    expected_total_esdt_reward: u64,
-) where
-    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
-{
-    let zero = num_bigint::ToBigUint::to_biguint(&0).unwrap();
+#[test]
+fn test_reward_token_allowlist() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let owner = sc_setup.owner_address.clone();
+    let caller1 = sc_setup.client_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    sc_setup.blockchain_wrapper.set_esdt_balance(&owner, INVALID_STAKE_TOKEN, &rust_biguint!(1_000));
 
-    setup.blockchain_wrapper
-        .execute_tx(caller, &setup.staking_sc_wrapper, &zero, |sc| {
-            sc.claim_reward();
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    sc_setup.blockchain_wrapper
+        .execute_esdt_transfer(&owner, &sc_setup.staking_sc_wrapper, INVALID_STAKE_TOKEN, 0, &rust_biguint!(1_000), |sc| {
+            sc.deposit_reward();
         })
-        .assert_ok();
+        .assert_user_error("reward token is not allowed");
 
-    let egld_balance = setup.blockchain_wrapper
-        .get_egld_balance(caller);
-    let esdt_balance = setup.blockchain_wrapper
-        .get_esdt_balance(caller, &REWARD_TOKEN, 0u64);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.allow_reward_token(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)), managed_biguint!(REWARD_AMOUNT));
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_esdt_transfer(&owner, &sc_setup.staking_sc_wrapper, REWARD_TOKEN, 0, &rust_biguint!(REWARD_AMOUNT - 1), |sc| {
+            sc.deposit_reward();
+        })
+        .assert_user_error("deposit below the minimum for the reward token");
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.disallow_reward_token(EgldOrEsdtTokenIdentifier::egld());
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(REWARD_AMOUNT), |sc| {
+            sc.deposit_reward();
+        })
+        .assert_user_error("reward token is not allowed");
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let allowed_tokens: Vec<_> = sc.get_allowed_reward_tokens().into_iter().map(|token| token.into_tuple()).collect();
+            assert_eq!(2, allowed_tokens.len());
+            assert!(allowed_tokens.contains(&(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)), managed_biguint!(REWARD_AMOUNT))));
+            assert!(allowed_tokens.contains(&(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(SECOND_REWARD_TOKEN)), managed_biguint!(0))));
+        })
+        .assert_ok();
+}
+
+#[test]
+fn test_sponsored_rewards() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let owner = sc_setup.owner_address.clone();
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let sponsor = sc_setup.client3_address.clone();
+    let open_sponsor = sc_setup.blockchain_wrapper.create_user_account(&rust_biguint!(0));
+    sc_setup.blockchain_wrapper.set_esdt_balance(&sponsor, SECOND_REWARD_TOKEN, &rust_biguint!(3_000));
+    sc_setup.blockchain_wrapper.set_esdt_balance(&open_sponsor, SECOND_REWARD_TOKEN, &rust_biguint!(3_000));
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    sponsor_reward(&mut sc_setup, &sponsor, 3_000).assert_user_error("token is not approved for sponsoring");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut tokens = MultiValueEncoded::new();
+            tokens.push(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(SECOND_REWARD_TOKEN)));
+            sc.add_sponsor_tokens(tokens);
+        })
+        .assert_ok();
+    sponsor_reward(&mut sc_setup, &sponsor, 3_000).assert_user_error("caller is not a sponsor");
+
+    // whitelisted sponsors pay no fee
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut sponsors = MultiValueEncoded::new();
+            sponsors.push(managed_address!(&sponsor));
+            sc.add_sponsors(sponsors);
+        })
+        .assert_ok();
+    // 30 tickets must each receive at least one unit
+    sponsor_reward(&mut sc_setup, &sponsor, 29).assert_user_error("sponsored reward below one unit per ticket");
+    sponsor_reward(&mut sc_setup, &sponsor, 3_000).assert_ok();
+
+    // anyone else pays the fee to the owner once open sponsoring is enabled
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    sponsor_reward(&mut sc_setup, &open_sponsor, 3_000).assert_user_error("caller is not a sponsor");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.set_sponsor_fee_percentage(1_000);
+        })
+        .assert_ok();
+    sponsor_reward(&mut sc_setup, &open_sponsor, 3_000).assert_ok();
+    sc_setup.blockchain_wrapper.check_esdt_balance(&owner, SECOND_REWARD_TOKEN, &rust_biguint!(10 * REWARD_AMOUNT + 300));
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let records = sc.get_reward_deposit_history(0, 10).to_vec();
+            assert_eq!(managed_address!(&sponsor), records.get(0).depositor);
+            assert_eq!(managed_biguint!(100), records.get(0).reward_per_ticket);
+            assert_eq!(managed_address!(&open_sponsor), records.get(1).depositor);
+            assert_eq!(managed_biguint!(2_700), records.get(1).amount);
+            assert_eq!(managed_biguint!(90), records.get(1).reward_per_ticket);
+        })
+        .assert_ok();
+
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, 0, 0);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, 0, 0);
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, SECOND_REWARD_TOKEN, &rust_biguint!(20 * 100 + 20 * 90));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller2, SECOND_REWARD_TOKEN, &rust_biguint!(10 * 100 + 10 * 90));
+}
+
+#[test]
+fn test_lifetime_earnings() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    stake_nfts(&mut sc_setup, &caller2, &[6, 7, 8]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 40);
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 80);
+
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, REWARD_AMOUNT / 4, REWARD_AMOUNT / 4);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(30u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 80);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, REWARD_AMOUNT / 2, REWARD_AMOUNT / 4);
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let egld = EgldOrEsdtTokenIdentifier::egld();
+            let reward_token = EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN));
+
+            let earnings: Vec<_> = sc.get_lifetime_earnings(managed_address!(&caller1)).into_iter().collect();
+            assert_eq!(2, earnings.len());
+            let (token, amount) = earnings[0].clone().into_tuple();
+            assert_eq!(egld, token);
+            assert_eq!(managed_biguint!(REWARD_AMOUNT / 2), amount);
+            let (token, amount) = earnings[1].clone().into_tuple();
+            assert_eq!(reward_token, token);
+            assert_eq!(managed_biguint!(REWARD_AMOUNT / 4), amount);
+
+            // caller2 has not claimed anything yet
+            assert!(sc.get_lifetime_earnings(managed_address!(&caller2)).is_empty());
+
+            let (deposited, claimed) = sc.get_reward_totals(egld).into_tuple();
+            assert_eq!(managed_biguint!(2 * REWARD_AMOUNT), deposited);
+            assert_eq!(managed_biguint!(REWARD_AMOUNT / 2), claimed);
+
+            let (deposited, claimed) = sc.get_reward_totals(reward_token).into_tuple();
+            assert_eq!(managed_biguint!(REWARD_AMOUNT), deposited);
+            assert_eq!(managed_biguint!(REWARD_AMOUNT / 4), claimed);
+        })
+        .assert_ok();
+}
+
+#[test]
+fn test_solvency_report() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    stake_nfts(&mut sc_setup, &caller2, &[6, 7]);
+
+    // 30 tickets, 1_000_000_000 / 30 leaves a remainder of 10 in the contract
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 30);
+    check_solvency_report(&mut sc_setup, &[(b"EGLD", REWARD_AMOUNT - 10, REWARD_AMOUNT)]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 60);
+    check_solvency_report(&mut sc_setup, &[
+        (b"EGLD", REWARD_AMOUNT - 10, REWARD_AMOUNT),
+        (REWARD_TOKEN, REWARD_AMOUNT - 40, REWARD_AMOUNT),
+    ]);
+
+    let c1_egld_rewards = REWARD_AMOUNT / 30 * 10;
+    let c1_esdt_rewards = REWARD_AMOUNT / 60 * 20;
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, c1_egld_rewards, c1_esdt_rewards);
+    check_solvency_report(&mut sc_setup, &[
+        (b"EGLD", REWARD_AMOUNT - 10 - c1_egld_rewards, REWARD_AMOUNT - c1_egld_rewards),
+        (REWARD_TOKEN, REWARD_AMOUNT - 40 - c1_esdt_rewards, REWARD_AMOUNT - c1_esdt_rewards),
+    ]);
+
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, REWARD_AMOUNT / 30 * 20, REWARD_AMOUNT / 60 * 40);
+    check_solvency_report(&mut sc_setup, &[(b"EGLD", 0, 10), (REWARD_TOKEN, 0, 40)]);
+
+    // budgets set aside for a reward schedule do not cover owed rewards
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(500), |sc| {
+            sc.fund_reward_schedule(managed_biguint!(100), 10, managed_biguint!(0));
+        })
+        .assert_ok();
+    check_solvency_report(&mut sc_setup, &[(b"EGLD", 0, 10), (REWARD_TOKEN, 0, 40)]);
+}
+
+#[test]
+fn test_seed_total_owed() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let owner = sc_setup.owner_address.clone();
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+
+    // an upgraded contract, with rewards booked before owed amounts were tracked
+    let sc_address = sc_setup.staking_sc_wrapper.address_ref().clone();
+    sc_setup.blockchain_wrapper.set_esdt_balance(&sc_address, REWARD_TOKEN, &rust_biguint!(350));
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.total_owed_seeding_closed().clear();
+            let reward_token = EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN));
+            sc.claimable_rewards(0, &managed_address!(&caller1))
+                .push(&EgldOrEsdtTokenPayment::new(reward_token.clone(), 0, managed_biguint!(100)));
+            sc.claimable_rewards(0, &managed_address!(&caller2))
+                .push(&EgldOrEsdtTokenPayment::new(reward_token.clone(), 0, managed_biguint!(200)));
+            sc.claimable_rewards(0, &managed_address!(&caller3))
+                .push(&EgldOrEsdtTokenPayment::new(reward_token, 0, managed_biguint!(50)));
+        })
+        .assert_ok();
+
+    // claiming before the seeding seeds the claiming address first
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, 0, 100);
+
+    seed_total_owed(&mut sc_setup, &[&caller1, &caller2, &caller2]).assert_ok();
+    check_solvency_report(&mut sc_setup, &[(REWARD_TOKEN, 200, 250)]);
+    seed_total_owed(&mut sc_setup, &[&caller2]).assert_ok();
+    check_solvency_report(&mut sc_setup, &[(REWARD_TOKEN, 200, 250)]);
+
+    // booking new rewards, even from a permissionless distribution, leaves seeding open
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert!(!sc.total_owed_seeding_closed().get());
+        })
+        .assert_ok();
+
+    // rewards that were never seeded cannot be claimed once the owner closes seeding
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.close_total_owed_seeding();
+        })
+        .assert_ok();
+    seed_total_owed(&mut sc_setup, &[&caller3]).assert_user_error("owed totals are already seeded");
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, 0, 200);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller3, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.claim_reward();
+        })
+        .assert_user_error("claimed reward exceeds the owed total");
+    check_solvency_report(&mut sc_setup, &[(REWARD_TOKEN, 0, 50), (b"EGLD", REWARD_AMOUNT, REWARD_AMOUNT)]);
+}
+
+#[test]
+fn test_historical_eligible_tickets() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(5u64);
+    stake_nfts(&mut sc_setup, &caller1, &[2, 3]);
+
+    // two stakes in the same epoch produce a single checkpoint
+    sc_setup.blockchain_wrapper.set_block_epoch(8u64);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+    stake_nfts(&mut sc_setup, &caller2, &[7]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    unstake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(15u64);
+    unstake_nfts(&mut sc_setup, &caller1, &[3]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+
+    // c1: 1 nft in [0, 5), 3 nfts in [5, 10), 1 nft in [10, 15)
+    // c2: 2 nfts since epoch 8
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(4, sc.stake_checkpoints(&managed_address!(&caller1)).len());
+            assert_eq!(1, sc.stake_checkpoints(&managed_address!(&caller2)).len());
+
+            assert_eq!(25, sc.get_historical_eligible_tickets(managed_address!(&caller1), 0, 20));
+            assert_eq!(11, sc.get_historical_eligible_tickets(managed_address!(&caller1), 7, 12));
+            assert_eq!(0, sc.get_historical_eligible_tickets(managed_address!(&caller1), 16, 20));
+            assert_eq!(24, sc.get_historical_eligible_tickets(managed_address!(&caller2), 0, 20));
+            assert_eq!(8, sc.get_historical_eligible_tickets(managed_address!(&caller2), 7, 12));
+            assert_eq!(0, sc.get_historical_eligible_tickets(managed_address!(&caller3), 0, 20));
+            assert_eq!(u64::MAX, sc.get_historical_eligible_tickets(managed_address!(&caller2), 0, u64::MAX));
+
+            // matches the current stake based computation for addresses that did not unstake
+            assert_eq!(
+                sc.get_total_payable_epochs(20, 0, &managed_address!(&caller2)),
+                sc.get_historical_eligible_tickets(managed_address!(&caller2), 0, 20)
+            );
+
+            let snapshot: Vec<_> = sc.get_eligible_tickets_snapshot(0, 20).into_iter().map(|entry| entry.into_tuple()).collect();
+            assert_eq!(vec![(managed_address!(&caller1), 25), (managed_address!(&caller2), 24)], snapshot);
+
+            let snapshot: Vec<_> = sc.get_eligible_tickets_snapshot(16, 20).into_iter().map(|entry| entry.into_tuple()).collect();
+            assert_eq!(vec![(managed_address!(&caller2), 8)], snapshot);
+        })
+        .assert_ok();
+}
+
+#[test]
+fn test_prize_inventory() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let owner = sc_setup.owner_address.clone();
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    sc_setup.blockchain_wrapper.set_nft_balance(&owner, PRIZE_TOKEN, 1, &rust_biguint!(1), &BoxedBytes::empty());
+    sc_setup.blockchain_wrapper.set_nft_balance(&owner, PRIZE_TOKEN, 2, &rust_biguint!(1), &BoxedBytes::empty());
+    sc_setup.blockchain_wrapper.set_nft_balance(&owner, PRIZE_TOKEN, 3, &rust_biguint!(3), &BoxedBytes::empty());
+
+    // NFTs cannot be split between tickets
+    sc_setup.blockchain_wrapper
+        .execute_esdt_transfer(&owner, &sc_setup.staking_sc_wrapper, PRIZE_TOKEN, 1, &rust_biguint!(1), |sc| {
+            sc.deposit_reward();
+        })
+        .assert_user_error("NFT and SFT rewards must be deposited as prizes");
+    sc_setup.blockchain_wrapper
+        .execute_esdt_transfer(&owner, &sc_setup.staking_sc_wrapper, REWARD_TOKEN, 0, &rust_biguint!(1_000), |sc| {
+            sc.deposit_prizes();
+        })
+        .assert_user_error("prizes must be NFTs or SFTs");
+
+    let prizes = vec![
+        TxInputESDT { token_identifier: Vec::from(PRIZE_TOKEN), nonce: 1, value: rust_biguint!(1) },
+        TxInputESDT { token_identifier: Vec::from(PRIZE_TOKEN), nonce: 2, value: rust_biguint!(1) },
+        TxInputESDT { token_identifier: Vec::from(PRIZE_TOKEN), nonce: 3, value: rust_biguint!(3) },
+    ];
+    sc_setup.blockchain_wrapper
+        .execute_esdt_multi_transfer(&owner, &sc_setup.staking_sc_wrapper, &prizes, |sc| {
+            sc.deposit_prizes();
+        })
+        .assert_ok();
+
+    // c1 holds 20 tickets and c2 10, the SFT units are handed out first
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.distribute_prizes(PrizeDistributionMode::TopHolders, 3);
+        })
+        .assert_ok();
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, 0, 0);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, 0, 0);
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, PRIZE_TOKEN, 3, &rust_biguint!(2), Option::None);
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller2, PRIZE_TOKEN, 3, &rust_biguint!(1), Option::None);
+
+    // each staker wins one of the two remaining NFTs
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.distribute_prizes(PrizeDistributionMode::WeightedRandom, 3);
+        })
+        .assert_user_error("not enough prizes in inventory");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.distribute_prizes(PrizeDistributionMode::WeightedRandom, 2);
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert!(sc.prize_inventory().is_empty());
+            assert_eq!(20, sc.last_prize_distribution_epoch().get());
+            assert_eq!(1, sc.get_claimable_rewards(managed_address!(&caller1)).into_iter().count());
+            assert_eq!(1, sc.get_claimable_rewards(managed_address!(&caller2)).into_iter().count());
+        })
+        .assert_ok();
+    check_invariants(&mut sc_setup);
+
+    // SFT lots adding up to more than a u64 are counted in full
+    let large_lot = rust_biguint!(i64::MAX as u64);
+    let mut large_prizes = Vec::new();
+    for nonce in 4..=6u64 {
+        sc_setup.blockchain_wrapper.set_nft_balance(&owner, PRIZE_TOKEN, nonce, &large_lot, &BoxedBytes::empty());
+        large_prizes.push(TxInputESDT { token_identifier: Vec::from(PRIZE_TOKEN), nonce, value: large_lot.clone() });
+    }
+    sc_setup.blockchain_wrapper
+        .execute_esdt_multi_transfer(&owner, &sc_setup.staking_sc_wrapper, &large_prizes, |sc| {
+            sc.deposit_prizes();
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper.set_block_epoch(30u64);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.distribute_prizes(PrizeDistributionMode::TopHolders, 2);
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(3, sc.prize_inventory().len());
+            assert_eq!(managed_biguint!(i64::MAX as u64 - 2), sc.prize_inventory().get(3).amount);
+        })
+        .assert_ok();
+}
+
+#[test]
+fn test_governance_voting() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    let proposal_id = create_proposal(&mut sc_setup, 10, false);
+    let weighted_proposal_id = create_proposal(&mut sc_setup, 6, true);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(3u64);
+    vote(&mut sc_setup, &caller1, proposal_id, 0).assert_ok();
+    vote(&mut sc_setup, &caller2, proposal_id, 1).assert_ok();
+    vote(&mut sc_setup, &caller1, proposal_id, 1).assert_user_error("already voted");
+    vote(&mut sc_setup, &caller3, proposal_id, 0).assert_user_error("only stakers can vote");
+    vote(&mut sc_setup, &caller3, proposal_id, 2).assert_user_error("invalid option");
+    vote(&mut sc_setup, &caller3, 3, 0).assert_user_error("proposal does not exist");
+
+    // c2 has staked one nft for 4 epochs, counting the current one
+    vote(&mut sc_setup, &caller2, weighted_proposal_id, 0).assert_ok();
+
+    // NFTs used to vote are locked until the proposal with the latest end ends
+    sc_setup.blockchain_wrapper.set_block_epoch(6u64);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller2, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut nonces = MultiValueEncoded::new();
+            nonces.push((6u64, 1u64).into());
+            sc.unstake(nonces);
+        })
+        .assert_user_error("nonce 6 is locked by a vote until epoch 10");
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    vote(&mut sc_setup, &caller1, weighted_proposal_id, 0).assert_user_error("proposal has ended");
+    unstake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    unstake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    check_proposal_results(&mut sc_setup, proposal_id, &[2, 1]);
+    check_proposal_results(&mut sc_setup, weighted_proposal_id, &[4, 0]);
+}
+
+#[test]
+fn test_raffle() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    stake_nfts(&mut sc_setup, &caller2, &[6, 7]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(1_000), |sc| {
+            sc.raffle(3);
+        })
+        .assert_user_error("not enough eligible stakers");
+
+    // both stakers win half of the prize
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(1_000), |sc| {
+            assert_eq!(1, sc.raffle(2));
+        })
+        .assert_ok();
+    check_invariants(&mut sc_setup);
+
+    sc_setup.blockchain_wrapper.set_nft_balance(&sc_setup.owner_address, STAKE_TOKEN, 20, &rust_biguint!(1), &BoxedBytes::empty());
+    sc_setup.blockchain_wrapper
+        .execute_esdt_transfer(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, STAKE_TOKEN, 20, &rust_biguint!(1), |sc| {
+            sc.raffle(1);
+        })
+        .assert_user_error("the stake token cannot be a prize");
+
+    // NFT prize, only tickets accumulated since the previous raffle count
+    sc_setup.blockchain_wrapper.set_nft_balance(&sc_setup.owner_address, PRIZE_TOKEN, 1, &rust_biguint!(1), &BoxedBytes::empty());
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    sc_setup.blockchain_wrapper
+        .execute_esdt_transfer(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, PRIZE_TOKEN, 1, &rust_biguint!(1), |sc| {
+            assert_eq!(2, sc.raffle(1));
+        })
+        .assert_ok();
+
+    let mut nft_winner = Address::zero();
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(20, sc.last_raffle_epoch().get());
+            // prizes are not reward deposits
+            assert_eq!(0, sc.get_reward_tokens().into_iter().count());
+
+            let first_raffle = sc.get_raffle(1);
+            assert_eq!(10, first_raffle.epoch);
+            assert_eq!(EgldOrEsdtTokenIdentifier::egld(), first_raffle.prize.token_identifier);
+            assert_eq!(managed_biguint!(500), first_raffle.prize.amount);
+            assert_eq!(managed_biguint!(0), first_raffle.remainder);
+            assert_eq!(2, first_raffle.winners.len());
+            assert!(first_raffle.winners.contains(&managed_address!(&caller1)));
+            assert!(first_raffle.winners.contains(&managed_address!(&caller2)));
+
+            let second_raffle = sc.get_raffle(2);
+            assert_eq!(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(PRIZE_TOKEN)), second_raffle.prize.token_identifier);
+            assert_eq!(1, second_raffle.prize.token_nonce);
+            assert_eq!(1, second_raffle.winners.len());
+            nft_winner = second_raffle.winners.get(0).to_address();
+        })
+        .assert_ok();
+
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, 500, 0);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, 500, 0);
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&nft_winner, PRIZE_TOKEN, 1, &rust_biguint!(1), Option::None);
+
+    // an uneven prize gives the remainder to the first winner
+    sc_setup.blockchain_wrapper.set_block_epoch(30u64);
+    let mut first_winner = Address::zero();
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(1_001), |sc| {
+            let raffle_id = sc.raffle(2);
+            let raffle = sc.get_raffle(raffle_id);
+            assert_eq!(managed_biguint!(500), raffle.prize.amount);
+            assert_eq!(managed_biguint!(1), raffle.remainder);
+            first_winner = raffle.winners.get(0).to_address();
+        })
+        .assert_ok();
+    check_invariants(&mut sc_setup);
+
+    let (first_prize_1, first_prize_2) = if first_winner == caller1 { (501, 500) } else { (500, 501) };
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, 500 + first_prize_1, 0);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, 500 + first_prize_2, 0);
+    sc_setup.blockchain_wrapper.check_egld_balance(sc_setup.staking_sc_wrapper.address_ref(), &rust_biguint!(0));
+}
+
+#[test]
+fn test_scheduled_distribution() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    // 1_000 per 10 epochs, 100 tip for whoever triggers the distribution
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(2_500), |sc| {
+            sc.fund_reward_schedule(managed_biguint!(1_000), 10, managed_biguint!(100));
+        })
+        .assert_ok();
+
+    sc_setup.blockchain_wrapper.set_block_epoch(5u64);
+    distribute(&mut sc_setup, &caller3, b"EGLD").assert_user_error("reward period has not elapsed yet");
+    distribute(&mut sc_setup, &caller3, REWARD_TOKEN).assert_user_error("no reward schedule for token");
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    distribute(&mut sc_setup, &caller3, b"EGLD").assert_ok();
+    check_invariants(&mut sc_setup);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(15u64);
+    distribute(&mut sc_setup, &caller3, b"EGLD").assert_user_error("reward period has not elapsed yet");
+
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    distribute(&mut sc_setup, &caller3, b"EGLD").assert_ok();
+    check_invariants(&mut sc_setup);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(30u64);
+    distribute(&mut sc_setup, &caller3, b"EGLD").assert_user_error("reward budget exhausted");
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let schedule = sc.reward_schedule(&EgldOrEsdtTokenIdentifier::egld()).get();
+            assert_eq!(managed_biguint!(300), schedule.budget);
+            assert_eq!(30, schedule.next_distribution_epoch);
+        })
+        .assert_ok();
+
+    // a disallowed token can no longer be distributed, but its budget can still be refunded
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.disallow_reward_token(EgldOrEsdtTokenIdentifier::egld());
+        })
+        .assert_ok();
+    distribute(&mut sc_setup, &caller3, b"EGLD").assert_user_error("reward token is not allowed");
+
+    // the remaining budget goes back to the owner
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.cancel_reward_schedule(EgldOrEsdtTokenIdentifier::egld());
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .check_egld_balance(&sc_setup.owner_address, &rust_biguint!(10 * REWARD_AMOUNT - 2_200));
+
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller3, &rust_biguint!(200));
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, 1_000, 0);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, 1_000, 0);
+}
+
+#[test]
+fn test_staking_set_bonus() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+
+    // 50% bonus for staking nonces 1, 2 and 3 together
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut nonces = MultiValueEncoded::new();
+            for nonce in [1u64, 2, 3] {
+                nonces.push(nonce);
+            }
+            sc.set_staking_set(1, 5_000, nonces);
+        })
+        .assert_ok();
+
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+    sc_setup.blockchain_wrapper.set_block_epoch(5u64);
+    check_active_set_bonuses(&mut sc_setup, &caller1, &[]);
+    check_total_payable_epochs(&mut sc_setup, &caller1, 0, 5, 10);
+
+    stake_nfts(&mut sc_setup, &caller1, &[3]);
+    check_active_set_bonuses(&mut sc_setup, &caller1, &[(1, 5_000)]);
+    check_active_set_bonuses(&mut sc_setup, &caller2, &[]);
+
+    // base: 10 + 10 + 5 = 25, bonus only since the set was completed at epoch 5: 15 * 50% = 7
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    check_total_payable_epochs(&mut sc_setup, &caller1, 0, 10, 32);
+    check_total_payable_epochs(&mut sc_setup, &caller2, 0, 10, 10);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 42);
+
+    // a full round with the set complete: 30 + 30 * 50%
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    check_total_payable_epochs(&mut sc_setup, &caller1, 10, 20, 45);
+
+    // the stake history weighs set bonuses the same way
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(32, sc.get_historical_eligible_tickets(managed_address!(&caller1), 0, 10));
+            assert_eq!(45, sc.get_historical_eligible_tickets(managed_address!(&caller1), 10, 20));
+        })
+        .assert_ok();
+
+    // the bonus only applies while the full set is staked
+    unstake_nfts(&mut sc_setup, &caller1, &[3]);
+    check_active_set_bonuses(&mut sc_setup, &caller1, &[]);
+    check_total_payable_epochs(&mut sc_setup, &caller1, 10, 20, 20);
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.remove_staking_set(1);
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.remove_staking_set(1);
+        })
+        .assert_user_error("set does not exist");
+
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, REWARD_AMOUNT / 42 * 32, 0);
+}
+
+#[test]
+fn test_eligibility_rules() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    sc_setup.blockchain_wrapper.set_nft_balance(&caller1, STAKE_TOKEN, 16, &rust_biguint!(1), &BoxedBytes::from(&b"burned:true"[..]));
+    sc_setup.blockchain_wrapper.set_nft_balance(&caller1, STAKE_TOKEN, 17, &rust_biguint!(1), &BoxedBytes::from(&b"revealed:true"[..]));
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut denied_nonces = MultiValueEncoded::new();
+            denied_nonces.push(2u64);
+            sc.add_denied_nonces(denied_nonces);
+            sc.add_denied_attribute_prefix(managed_buffer!(b"burned"));
+            sc.add_eligible_nonce_range(1, 4);
+            sc.add_eligible_nonce_range(15, 20);
+        })
+        .assert_ok();
+
+    try_stake_nfts(&mut sc_setup, &caller1, &[1, 2]).assert_user_error("nonce 2 is denylisted");
+    try_stake_nfts(&mut sc_setup, &caller1, &[5]).assert_user_error("nonce 5 is outside the eligible nonce ranges");
+    try_stake_nfts(&mut sc_setup, &caller1, &[16]).assert_user_error("nonce 16 has denied attributes");
+
+    stake_nfts(&mut sc_setup, &caller1, &[1, 17]);
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut denied_nonces = MultiValueEncoded::new();
+            denied_nonces.push(2u64);
+            sc.remove_denied_nonces(denied_nonces);
+            sc.clear_eligible_nonce_ranges();
+        })
+        .assert_ok();
+
+    stake_nfts(&mut sc_setup, &caller1, &[2, 5]);
+}
+
+#[test]
+fn test_staking_limits() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+    set_staking_limits(&mut sc_setup, 3, 5, 2);
+
+    try_stake_nfts(&mut sc_setup, &caller1, &[1]).assert_user_error("stake below minimum NFTs per stake call");
+    try_stake_nfts(&mut sc_setup, &caller1, &[1, 2, 3, 4]).assert_user_error("stake exceeds maximum NFTs per address");
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    try_stake_nfts(&mut sc_setup, &caller1, &[3, 4]).assert_user_error("stake exceeds maximum NFTs per address");
+    try_stake_nfts(&mut sc_setup, &caller2, &[6, 7, 8, 9]).assert_user_error("stake exceeds maximum NFTs per address");
+    stake_nfts(&mut sc_setup, &caller2, &[6, 7, 8]);
+    try_stake_nfts(&mut sc_setup, &caller3, &[11, 12]).assert_user_error("stake exceeds maximum total staked");
+
+    unstake_nfts(&mut sc_setup, &caller1, &[1]);
+    try_stake_nfts(&mut sc_setup, &caller3, &[11, 12]).assert_user_error("stake exceeds maximum total staked");
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(4, sc.total_staked().get());
+            assert_eq!((3, 5, 2), sc.get_staking_limits().into_tuple());
+        })
+        .assert_ok();
+
+    // zero removes the limits
+    set_staking_limits(&mut sc_setup, 0, 0, 0);
+    stake_nfts(&mut sc_setup, &caller3, &[11, 12, 13, 14, 15]);
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(9, sc.total_staked().get());
+        })
+        .assert_ok();
+}
+
+#[test]
+fn test_seasons() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            assert_eq!(1, sc.create_season(10, 20));
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.create_season(20, 20);
+        })
+        .assert_user_error("season must end after it starts");
+
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    sc_setup.blockchain_wrapper.set_block_epoch(5u64);
+    deposit_season_esdt_rewards(&mut sc_setup, 1).assert_user_error("season has not started");
+    deposit_season_esdt_rewards(&mut sc_setup, 2).assert_user_error("season does not exist");
+
+    sc_setup.blockchain_wrapper.set_block_epoch(15u64);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+    sc_setup.blockchain_wrapper.set_block_epoch(25u64);
+    stake_nfts(&mut sc_setup, &caller3, &[11]);
+
+    // season 1 only covers epochs 10 to 20: c1 = 10, c2 = 5, c3 = 0
+    sc_setup.blockchain_wrapper.set_block_epoch(30u64);
+    deposit_season_esdt_rewards(&mut sc_setup, 1).assert_ok();
+    // the default pool covers epochs 0 to 30: c1 = 30, c2 = 15, c3 = 5
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 50);
+
+    let c1_season_rewards = REWARD_AMOUNT / 15 * 10;
+    let c2_season_rewards = REWARD_AMOUNT / 15 * 5;
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let season_rewards = sc.claimable_rewards(1, &managed_address!(&caller1));
+            assert_eq!(1, season_rewards.len());
+            assert_eq!(managed_biguint!(c1_season_rewards), season_rewards.get(1).amount);
+            assert!(sc.claimable_rewards(1, &managed_address!(&caller3)).is_empty());
+
+            assert_eq!(2, sc.get_claimable_rewards(managed_address!(&caller1)).into_iter().count());
+
+            let season_info = sc.get_season_reward_payment_info(1, EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)));
+            assert_eq!(20, season_info.last_paid_epoch);
+            assert_eq!(managed_biguint!(REWARD_AMOUNT / 15), season_info.last_reward_per_epoch_per_nonce);
+            let default_info = sc.get_reward_payment_info(EgldOrEsdtTokenIdentifier::egld());
+            assert_eq!(30, default_info.last_paid_epoch);
+
+            // each season tracks its own reward tokens and deposits
+            let season_tokens: Vec<_> = sc.reward_tokens(1).iter().collect();
+            assert_eq!(vec![EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN))], season_tokens);
+            let default_tokens: Vec<_> = sc.get_reward_tokens().into_iter().collect();
+            assert_eq!(vec![EgldOrEsdtTokenIdentifier::egld()], default_tokens);
+            assert_eq!(1, sc.get_season_recent_reward_deposits(1, EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN))).len());
+            assert!(sc.get_recent_reward_deposits(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN))).is_empty());
+        })
+        .assert_ok();
+
+    // the whole season was already paid
+    sc_setup.blockchain_wrapper.set_block_epoch(40u64);
+    deposit_season_esdt_rewards(&mut sc_setup, 1).assert_user_error("no eligible tickets");
+
+    // claiming a single season leaves the other pools untouched
+    claim_season_rewards(&mut sc_setup, &caller2, 1).assert_ok();
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller2, REWARD_TOKEN, &rust_biguint!(c2_season_rewards));
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller2, &rust_biguint!(0));
+    claim_season_rewards(&mut sc_setup, &caller2, 1).assert_user_error("no rewards to claim");
+    check_invariants(&mut sc_setup);
+
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, REWARD_AMOUNT / 50 * 30, c1_season_rewards);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, REWARD_AMOUNT / 50 * 15, c2_season_rewards);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller3, REWARD_AMOUNT / 50 * 5, 0);
+}
+
+#[test]
+fn test_legacy_reward_storage() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let owner = sc_setup.owner_address.clone();
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    // keys written before seasons existed carry no season id
+    let sc_address = sc_setup.staking_sc_wrapper.address_ref().clone();
+    sc_setup.blockchain_wrapper.set_esdt_balance(&sc_address, REWARD_TOKEN, &rust_biguint!(1_000));
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |_| {
+            let reward_token = EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN));
+            let mut payment_info_key = StorageKey::new(b"reward_payment_info");
+            payment_info_key.append_item(&reward_token);
+            SingleValueMapper::<DebugApi, RewardPaymentInfo<DebugApi>>::new(payment_info_key).set(&RewardPaymentInfo {
+                last_paid_epoch: 5,
+                total_paid_so_far: managed_biguint!(1_000),
+                last_reward_payment: managed_biguint!(1_000),
+                last_reward_per_epoch_per_nonce: managed_biguint!(100),
+            });
+
+            let mut claimable_rewards_key = StorageKey::new(b"claimable_rewards");
+            claimable_rewards_key.append_item(&ManagedAddress::<DebugApi>::from_address(&caller1));
+            VecMapper::<DebugApi, EgldOrEsdtTokenPayment<DebugApi>>::new(claimable_rewards_key)
+                .push(&EgldOrEsdtTokenPayment::new(reward_token, 0, managed_biguint!(1_000)));
+
+            // owed totals of an upgraded contract still have to be seeded
+            SingleValueMapper::<DebugApi, bool>::new(StorageKey::new(b"total_owed_seeding_closed")).clear();
+        })
+        .assert_ok();
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let payment_info = sc.get_reward_payment_info(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)));
+            assert_eq!(5, payment_info.last_paid_epoch);
+            assert_eq!(managed_biguint!(1_000), payment_info.total_paid_so_far);
+            assert_eq!(1, sc.get_claimable_rewards(managed_address!(&caller1)).into_iter().count());
+            assert_eq!(0, sc.get_all_reward_payment_info().into_iter().count());
+        })
+        .assert_ok();
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, 0, 1_000);
+
+    // tokens deposited before the upgrade are only listed once backfilled
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut tokens = MultiValueEncoded::new();
+            tokens.push(EgldOrEsdtTokenIdentifier::egld());
+            sc.backfill_reward_tokens(tokens);
+        })
+        .assert_user_error("token was never deposited as a reward");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut tokens = MultiValueEncoded::new();
+            tokens.push(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)));
+            sc.backfill_reward_tokens(tokens);
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let all_info: Vec<_> = sc.get_all_reward_payment_info().into_iter().map(|info| info.into_tuple()).collect();
+            assert_eq!(1, all_info.len());
+            assert_eq!(EgldOrEsdtTokenIdentifier::esdt(managed_token_id!(REWARD_TOKEN)), all_info[0].0);
+            assert_eq!(managed_biguint!(1_000), all_info[0].1.total_paid_so_far);
+        })
+        .assert_ok();
+
+    // the next deposit continues from the epoch of the last one made before the upgrade
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, 0, REWARD_AMOUNT / 2);
+}
+
+#[test]
+fn test_referral_rewards() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let caller3 = sc_setup.client3_address.clone();
+
+    // referrers earn 10% of their referrals' tickets
+    sc_setup.blockchain_wrapper
+        .execute_tx(&sc_setup.owner_address, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.set_referral_bonus_percentage(1_000);
+        })
+        .assert_ok();
+
+    try_stake_nfts_referred(&mut sc_setup, &caller1, &[1], &caller1).assert_user_error("cannot refer yourself");
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    try_stake_nfts_referred(&mut sc_setup, &caller2, &[6, 7, 8, 9, 10], &caller1).assert_ok();
+    try_stake_nfts_referred(&mut sc_setup, &caller3, &[11], &caller1).assert_ok();
+    // only the first stake sets the referrer
+    try_stake_nfts_referred(&mut sc_setup, &caller3, &[12], &caller2).assert_ok();
+
+    // tickets: c1 = 10, c2 = 50, c3 = 20; referral bonus for c1: 5 + 2
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    let expected_unit_reward = REWARD_AMOUNT / 87;
+    check_ticket_share(&mut sc_setup, &caller1, b"EGLD", 17, 87);
+    check_preview_reward(&mut sc_setup, &caller1, b"EGLD", REWARD_AMOUNT, expected_unit_reward * 17);
+    deposit_egld_rewards(&mut sc_setup, expected_unit_reward);
+
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert_eq!(Some(managed_address!(&caller1)), sc.get_referrer(managed_address!(&caller3)).into_option());
+            assert_eq!(None, sc.get_referrer(managed_address!(&caller1)).into_option());
+            assert_eq!(2, sc.get_referral_count(managed_address!(&caller1)));
+            assert_eq!(0, sc.get_referral_count(managed_address!(&caller2)));
+            assert_eq!(
+                managed_biguint!(expected_unit_reward * 7),
+                sc.referral_earnings(&managed_address!(&caller1), &EgldOrEsdtTokenIdentifier::egld()).get()
+            );
+        })
+        .assert_ok();
+
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, expected_unit_reward * 17, 0);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller2, expected_unit_reward * 50, 0);
+    claim_rewards_and_check_balance(&mut sc_setup, &caller3, expected_unit_reward * 20, 0);
+}
+
+#[test]
+fn test_reward_destination() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    let cold_wallet_receiver = sc_setup.client3_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 30);
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller1, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.claim_reward_to(managed_address!(&cold_wallet_receiver));
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller1, &rust_biguint!(0));
+    sc_setup.blockchain_wrapper.check_egld_balance(&cold_wallet_receiver, &rust_biguint!(20 * (REWARD_AMOUNT / 30)));
+
+    // the destination is honoured by end staking, while the NFTs go back to the staker
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller1, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.set_reward_destination(OptionalValue::Some(managed_address!(&cold_wallet_receiver)));
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            let destination = sc.get_reward_destination(managed_address!(&caller1)).into_option();
+            assert_eq!(Some(managed_address!(&cold_wallet_receiver)), destination);
+            assert!(sc.get_reward_destination(managed_address!(&caller2)).into_option().is_none());
+        })
+        .assert_ok();
+
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 30);
+    trigger_end_stake(&mut sc_setup);
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller1, &rust_biguint!(0));
+    sc_setup.blockchain_wrapper.check_egld_balance(&cold_wallet_receiver, &rust_biguint!(40 * (REWARD_AMOUNT / 30)));
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller2, &rust_biguint!(20 * (REWARD_AMOUNT / 30)));
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, STAKE_TOKEN, 1, &rust_biguint!(1), Option::None);
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller1, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.set_reward_destination(OptionalValue::None);
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_query(&sc_setup.staking_sc_wrapper, |sc| {
+            assert!(sc.get_reward_destination(managed_address!(&caller1)).into_option().is_none());
+        })
+        .assert_ok();
+}
+
+#[test]
+fn test_exit_and_unstake_and_claim() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let caller1 = sc_setup.client_address.clone();
+    let caller2 = sc_setup.client2_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1, 2, 3]);
+    stake_nfts(&mut sc_setup, &caller2, &[6]);
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 40);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 40);
+
+    unstake_and_claim(&mut sc_setup, &caller1, &[1]).assert_ok();
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller1, &rust_biguint!(30 * (REWARD_AMOUNT / 40)));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, REWARD_TOKEN, &rust_biguint!(30 * (REWARD_AMOUNT / 40)));
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, STAKE_TOKEN, 1, &rust_biguint!(1), Option::None);
+    check_invariants(&mut sc_setup);
+
+    // nothing left to claim is not an error
+    unstake_and_claim(&mut sc_setup, &caller1, &[2]).assert_ok();
+    unstake_and_claim(&mut sc_setup, &caller1, &[]).assert_user_error("no nonces to unstake");
+
+    exit(&mut sc_setup, &caller2).assert_ok();
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller2, &rust_biguint!(10 * (REWARD_AMOUNT / 40)));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller2, REWARD_TOKEN, &rust_biguint!(10 * (REWARD_AMOUNT / 40)));
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller2, STAKE_TOKEN, 6, &rust_biguint!(1), Option::None);
+
+    exit(&mut sc_setup, &caller1).assert_ok();
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, STAKE_TOKEN, 3, &rust_biguint!(1), Option::None);
+    exit(&mut sc_setup, &caller1).assert_user_error("nothing to unstake or claim");
+    check_invariants(&mut sc_setup);
+
+    // rewards alone are enough to exit
+    let caller3 = sc_setup.client3_address.clone();
+    stake_nfts(&mut sc_setup, &caller3, &[11]);
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+    unstake_nfts(&mut sc_setup, &caller3, &[11]);
+    exit(&mut sc_setup, &caller3).assert_ok();
+    sc_setup.blockchain_wrapper.check_egld_balance(&caller3, &rust_biguint!(REWARD_AMOUNT));
+    exit(&mut sc_setup, &caller3).assert_user_error("nothing to unstake or claim");
+    check_invariants(&mut sc_setup);
+}
+
+#[test]
+fn test_marketplace_integrator() {
+    use marketplace_mock::MarketplaceMock;
+
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let owner = sc_setup.owner_address.clone();
+    let seller = sc_setup.client_address.clone();
+    let buyer = sc_setup.client3_address.clone();
+    sc_setup.blockchain_wrapper.set_egld_balance(&buyer, &rust_biguint!(3_000));
+    stake_nfts(&mut sc_setup, &seller, &[1, 2]);
+
+    let marketplace_wrapper = sc_setup.blockchain_wrapper.create_sc_account(
+        &rust_biguint!(0),
+        Some(&owner),
+        marketplace_mock::contract_obj,
+        MARKETPLACE_WASM_PATH,
+    );
+    let staking_address = sc_setup.staking_sc_wrapper.address_ref().clone();
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &marketplace_wrapper, &rust_biguint!(0), |sc| {
+            sc.init(managed_address!(&staking_address));
+        })
+        .assert_ok();
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.add_integrator(managed_address!(&buyer));
+        })
+        .assert_user_error("integrator must be a smart contract");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.add_integrator(managed_address!(marketplace_wrapper.address_ref()));
+        })
+        .assert_ok();
+
+    // the seller has to approve the marketplace first
+    sc_setup.blockchain_wrapper
+        .execute_tx(&buyer, &marketplace_wrapper, &rust_biguint!(1_000), |sc| {
+            sc.buy_staked_nft(managed_address!(&seller), 1);
+        })
+        .assert_user_error("integrator not approved by staker");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&seller, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.approve_integrator(managed_address!(marketplace_wrapper.address_ref()));
+        })
+        .assert_ok();
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&buyer, &marketplace_wrapper, &rust_biguint!(1_000), |sc| {
+            sc.buy_staked_nft(managed_address!(&seller), 3);
+        })
+        .assert_user_error("NFT is not staked");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&buyer, &marketplace_wrapper, &rust_biguint!(1_000), |sc| {
+            sc.buy_staked_nft(managed_address!(&seller), 1);
+        })
+        .assert_ok();
+    check_invariants(&mut sc_setup);
+    sc_setup.blockchain_wrapper
+        .check_nft_balance::<ManagedBuffer<DebugApi>>(&buyer, STAKE_TOKEN, 1, &rust_biguint!(1), Option::None);
+    sc_setup.blockchain_wrapper.check_egld_balance(&seller, &rust_biguint!(1_000));
+    check_nonce_stakers(&mut sc_setup, 1, &[]);
+    check_nonce_stakers(&mut sc_setup, 2, &[&seller]);
+
+    sc_setup.blockchain_wrapper
+        .execute_tx(&seller, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.revoke_integrator(managed_address!(marketplace_wrapper.address_ref()));
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper
+        .execute_tx(&buyer, &marketplace_wrapper, &rust_biguint!(1_000), |sc| {
+            sc.buy_staked_nft(managed_address!(&seller), 2);
+        })
+        .assert_user_error("integrator not approved by staker");
+}
+
+#[test]
+fn test_swapped_reward_claim() {
+    use dex_pair_mock::DexPairMock;
+
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+    let owner = sc_setup.owner_address.clone();
+    let caller1 = sc_setup.client_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+
+    let pair_wrapper = sc_setup.blockchain_wrapper.create_sc_account(
+        &rust_biguint!(0),
+        Some(&owner),
+        dex_pair_mock::contract_obj,
+        DEX_PAIR_WASM_PATH,
+    );
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &pair_wrapper, &rust_biguint!(0), |sc| {
+            sc.init(2);
+        })
+        .assert_ok();
+    sc_setup.blockchain_wrapper.set_esdt_balance(pair_wrapper.address_ref(), SECOND_REWARD_TOKEN, &rust_biguint!(10 * REWARD_AMOUNT));
+
+    sc_setup.blockchain_wrapper.set_block_epoch(10u64);
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+
+    claim_swapped_reward(&mut sc_setup, &caller1, 0).assert_user_error("no preferred reward token set");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&caller1, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.set_preferred_reward_token(OptionalValue::Some(managed_token_id!(SECOND_REWARD_TOKEN)));
+        })
+        .assert_ok();
+    claim_swapped_reward(&mut sc_setup, &caller1, 0).assert_user_error("no swap pair for token");
+    sc_setup.blockchain_wrapper
+        .execute_tx(&owner, &sc_setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.set_swap_pair(
+                managed_token_id!(REWARD_TOKEN),
+                managed_token_id!(SECOND_REWARD_TOKEN),
+                managed_address!(pair_wrapper.address_ref()),
+            );
+        })
+        .assert_ok();
+
+    // the pair would pay less than the minimum, so the original rewards are refunded
+    claim_swapped_reward(&mut sc_setup, &caller1, 2 * REWARD_AMOUNT + 1).assert_ok();
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, REWARD_TOKEN, &rust_biguint!(REWARD_AMOUNT));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, SECOND_REWARD_TOKEN, &rust_biguint!(0));
+    check_invariants(&mut sc_setup);
+
+    // only what the pair actually sends back is paid out, not the inflated amount it reports
+    let pair_address = pair_wrapper.address_ref().clone();
+    sc_setup.blockchain_wrapper.set_block_epoch(20u64);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+    simulate_swap_callback(&mut sc_setup, &pair_address, &caller1, SECOND_REWARD_TOKEN, 2 * REWARD_AMOUNT, 20 * REWARD_AMOUNT, 2 * REWARD_AMOUNT);
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, REWARD_TOKEN, &rust_biguint!(REWARD_AMOUNT));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, SECOND_REWARD_TOKEN, &rust_biguint!(2 * REWARD_AMOUNT));
+    check_invariants(&mut sc_setup);
+
+    // a pair sending back another token gets the original rewards refunded
+    sc_setup.blockchain_wrapper.set_esdt_balance(&pair_address, INVALID_STAKE_TOKEN, &rust_biguint!(10 * REWARD_AMOUNT));
+    sc_setup.blockchain_wrapper.set_block_epoch(30u64);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+    simulate_swap_callback(&mut sc_setup, &pair_address, &caller1, INVALID_STAKE_TOKEN, 2 * REWARD_AMOUNT, 2 * REWARD_AMOUNT, 2 * REWARD_AMOUNT);
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, REWARD_TOKEN, &rust_biguint!(2 * REWARD_AMOUNT));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, SECOND_REWARD_TOKEN, &rust_biguint!(2 * REWARD_AMOUNT));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, INVALID_STAKE_TOKEN, &rust_biguint!(0));
+    check_invariants(&mut sc_setup);
+
+    // as does a pair sending back less than the minimum while reporting enough
+    sc_setup.blockchain_wrapper.set_block_epoch(40u64);
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / 10);
+    simulate_swap_callback(&mut sc_setup, &pair_address, &caller1, SECOND_REWARD_TOKEN, REWARD_AMOUNT, 2 * REWARD_AMOUNT, 2 * REWARD_AMOUNT);
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, REWARD_TOKEN, &rust_biguint!(3 * REWARD_AMOUNT));
+    sc_setup.blockchain_wrapper.check_esdt_balance(&caller1, SECOND_REWARD_TOKEN, &rust_biguint!(2 * REWARD_AMOUNT));
+    check_invariants(&mut sc_setup);
+    claim_swapped_reward(&mut sc_setup, &caller1, 0).assert_user_error("no rewards to claim");
+
+    // rewards in other tokens stay claimable
+    claim_rewards_and_check_balance(&mut sc_setup, &caller1, REWARD_AMOUNT, 3 * REWARD_AMOUNT);
+}
+
+#[test]
+fn test_end_stake() {
+    let _ = DebugApi::dummy();
+    let mut sc_setup = setup_staking(cnuns_staking::contract_obj);
+
+    let caller1 = sc_setup.client_address.clone();
+    stake_nfts(&mut sc_setup, &caller1, &[1]);
+    
+    // distribute EGLD rewards in epoch 10
+    let mut block_epoch = 10u64;
+    sc_setup.blockchain_wrapper.set_block_epoch(block_epoch);
+
+    deposit_egld_rewards(&mut sc_setup, REWARD_AMOUNT / block_epoch);
+
+    // distribute ESDT rewards in epoch 20
+    block_epoch = 20u64;
+    sc_setup.blockchain_wrapper.set_block_epoch(block_epoch);
+
+    deposit_esdt_rewards(&mut sc_setup, REWARD_AMOUNT / block_epoch);
+    trigger_end_stake(&mut sc_setup);
+
+    sc_setup.blockchain_wrapper
+            .check_nft_balance::<ManagedBuffer<DebugApi>>(&caller1, STAKE_TOKEN, 1, &rust_biguint!(1), Option::None);
+
+    sc_setup.blockchain_wrapper
+        .check_esdt_balance(&caller1, REWARD_TOKEN, &rust_biguint!(REWARD_AMOUNT));
+    sc_setup.blockchain_wrapper
+        .check_egld_balance(&caller1, &rust_biguint!(REWARD_AMOUNT));
+
+}
+
+/* Helper functions */
+fn check_origin_epoch<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    expected: u64,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            let origin_epoch = sc.get_origin_epoch();
+            assert_eq!(expected, origin_epoch);
+        })
+        .assert_ok();
+}
+
+fn check_stake_token<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            let stake_token = sc.get_stake_token();
+            let expected = managed_token_id!(STAKE_TOKEN);
+            assert_eq!(expected, stake_token);
+        })
+        .assert_ok();
+}
+
+fn check_nonce_stakers<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    nonce: u64,
+    expected: &[&Address],
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            let stakers = sc.nonce_stakers(nonce);
+            assert_eq!(expected.len(), stakers.len());
+            for address in expected.iter() {
+                assert!(stakers.contains(&managed_address!(address)));
+            }
+
+            let owner = sc.get_nonce_owner(nonce).into_option();
+            if expected.len() == 1 {
+                assert_eq!(Some(managed_address!(expected[0])), owner);
+            } else {
+                assert_eq!(None, owner);
+            }
+        })
+        .assert_ok();
+}
+
+fn check_preview_reward<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    address: &Address,
+    token: &[u8],
+    amount: u64,
+    expected: u64,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            let token_identifier = EgldOrEsdtTokenIdentifier::parse(managed_buffer!(token));
+            let preview = sc.preview_reward(managed_address!(address), token_identifier, managed_biguint!(amount));
+            assert_eq!(managed_biguint!(expected), preview);
+        })
+        .assert_ok();
+}
+
+fn check_ticket_share<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    address: &Address,
+    token: &[u8],
+    expected_address_tickets: u64,
+    expected_total_tickets: u64,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            let token_identifier = EgldOrEsdtTokenIdentifier::parse(managed_buffer!(token));
+            let (address_tickets, total_tickets) = sc.get_ticket_share(managed_address!(address), token_identifier).into_tuple();
+            assert_eq!(expected_address_tickets, address_tickets);
+            assert_eq!(expected_total_tickets, total_tickets);
+        })
+        .assert_ok();
+}
+
+fn check_active_set_bonuses<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    address: &Address,
+    expected: &[(u32, u64)],
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            let bonuses: Vec<(u32, u64)> = sc.get_active_set_bonuses(managed_address!(address)).into_iter().map(|bonus| bonus.into_tuple()).collect();
+            assert_eq!(expected.to_vec(), bonuses);
+        })
+        .assert_ok();
+}
+
+// writes a stake the way it was stored before SFT amounts were tracked
+fn write_legacy_stake<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    address: &Address,
+    nonces_and_epochs: &[(u64, u64)],
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &rust_biguint!(0), |_| {
+            let mut legacy_stake = ManagedVec::new();
+            for (nonce, stake_epoch) in nonces_and_epochs.iter() {
+                legacy_stake.push(LegacyStakedNftInfo {
+                    nonce: *nonce,
+                    stake_epoch: *stake_epoch,
+                });
+            }
+            MapMapper::<DebugApi, ManagedAddress<DebugApi>, ManagedVec<DebugApi, LegacyStakedNftInfo>>::new(StorageKey::new(b"staked_nfts"))
+                .insert(ManagedAddress::from_address(address), legacy_stake);
+        })
+        .assert_ok();
+}
+
+fn migrate_legacy_stakes<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    max_addresses: usize,
+    expected_left: usize,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            assert_eq!(expected_left, sc.migrate_legacy_stakes(max_addresses));
+        })
+        .assert_ok();
+}
+
+fn stake_nfts<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    nonces: &[u64],
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    try_stake_nfts(setup, caller, nonces).assert_ok();
+
+    check_invariants(setup);
+}
+
+fn try_stake_nfts<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    nonces: &[u64],
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    let nft_balance = num_bigint::ToBigUint::to_biguint(&1).unwrap();
+
+    let mut vec = Vec::new();
+    for nonce in nonces.iter() {
+        let tx_input = TxInputESDT {
+            token_identifier: Vec::from(STAKE_TOKEN),
+            nonce: *nonce,
+            value: nft_balance.clone()
+        };
+        vec.push(tx_input);
+    }
+    
+    setup.blockchain_wrapper
+        .execute_esdt_multi_transfer(caller, &setup.staking_sc_wrapper, &vec, |sc| {
+            sc.stake(OptionalValue::None);
+        })
+}
+
+fn try_stake_nfts_referred<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    nonces: &[u64],
+    referrer: &Address,
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    let mut vec = Vec::new();
+    for nonce in nonces.iter() {
+        vec.push(TxInputESDT {
+            token_identifier: Vec::from(STAKE_TOKEN),
+            nonce: *nonce,
+            value: rust_biguint!(1),
+        });
+    }
+
+    setup.blockchain_wrapper
+        .execute_esdt_multi_transfer(caller, &setup.staking_sc_wrapper, &vec, |sc| {
+            sc.stake(OptionalValue::Some(managed_address!(referrer)));
+        })
+}
+
+fn try_stake_sfts<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    nonces_and_amounts: &[(u64, u64)],
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    let mut vec = Vec::new();
+    for (nonce, amount) in nonces_and_amounts.iter() {
+        vec.push(TxInputESDT {
+            token_identifier: Vec::from(STAKE_TOKEN),
+            nonce: *nonce,
+            value: rust_biguint!(*amount),
+        });
+    }
+
+    setup.blockchain_wrapper
+        .execute_esdt_multi_transfer(caller, &setup.staking_sc_wrapper, &vec, |sc| {
+            sc.stake(OptionalValue::None);
+        })
+}
+
+fn try_unstake_sfts<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    nonces_and_amounts: &[(u64, u64)],
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut unstake_params = MultiValueEncoded::new();
+            for (nonce, amount) in nonces_and_amounts.iter() {
+                unstake_params.push((*nonce, *amount).into());
+            }
+            sc.unstake(unstake_params);
+        })
+}
+
+fn unstake_nfts<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    nonces: &[u64],
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    let nft_balance = num_bigint::ToBigUint::to_biguint(&1).unwrap();
+    let zero = num_bigint::ToBigUint::to_biguint(&0).unwrap();
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &zero, |sc| {
+            let mut unstake_params = MultiValueEncoded::new();
+            for nonce in nonces.iter() {
+                unstake_params.push((*nonce, 1u64).into());
+            }
+            sc.unstake(unstake_params);
+        })
+        .assert_ok();
+
+    for nonce in nonces.iter() {
+        setup.blockchain_wrapper
+            .check_nft_balance::<ManagedBuffer<DebugApi>>(caller, STAKE_TOKEN, *nonce, &nft_balance, Option::None);
+    }
+
+    check_invariants(setup);
+}
+
+fn unstake_and_claim<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    nonces: &[u64],
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut unstake_params = MultiValueEncoded::new();
+            for nonce in nonces.iter() {
+                unstake_params.push((*nonce, 1u64).into());
+            }
+            sc.unstake_and_claim(unstake_params);
+        })
+}
+
+fn exit<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.exit();
+        })
+}
+
+fn claim_swapped_reward<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    amount_out_min: u64,
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.claim_swapped_reward(managed_token_id!(REWARD_TOKEN), managed_biguint!(amount_out_min));
+        })
+}
+
+// the test framework does not hand the tokens sent back by the pair to the callback,
+// so the callback is called directly with `sent_amount` of `sent_token` attached
+fn simulate_swap_callback<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    pair_address: &Address,
+    caller: &Address,
+    sent_token: &[u8],
+    sent_amount: u64,
+    reported_amount: u64,
+    amount_out_min: u64,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_esdt_transfer(pair_address, &setup.staking_sc_wrapper, sent_token, 0, &rust_biguint!(sent_amount), |sc| {
+            let staker = managed_address!(caller);
+            let token = managed_token_id!(REWARD_TOKEN);
+            let amount = sc.take_claimable_token_rewards(&staker, &staker, &token);
+            let reported = EsdtTokenPayment::new(managed_token_id!(SECOND_REWARD_TOKEN), 0, managed_biguint!(reported_amount));
+            sc.swap_reward_callback(
+                staker,
+                token,
+                amount,
+                managed_token_id!(SECOND_REWARD_TOKEN),
+                managed_biguint!(amount_out_min),
+                ManagedAsyncCallResult::Ok(reported),
+            );
+        })
+        .assert_ok();
+}
+
+fn deposit_egld_rewards<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    expected_reward_per_unit: u64,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    let reward_amount = num_bigint::ToBigUint::to_biguint(&REWARD_AMOUNT).unwrap();
+    
+    setup.blockchain_wrapper
+        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &reward_amount, |sc| {
+            let reward_per_epoch_per_nonce = sc.deposit_reward();
+            assert_eq!(managed_biguint!(expected_reward_per_unit), reward_per_epoch_per_nonce);
+        })
+        .assert_ok();
+
+    check_invariants(setup);
+}
+
+fn deposit_esdt_rewards<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    expected_reward_per_unit: u64,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    let reward_amount = num_bigint::ToBigUint::to_biguint(&REWARD_AMOUNT).unwrap();
+    
+    setup.blockchain_wrapper
+        .execute_esdt_transfer(&setup.owner_address, &setup.staking_sc_wrapper, REWARD_TOKEN, 0u64, &reward_amount, |sc| {
+            let reward_per_epoch_per_nonce = sc.deposit_reward();
+            assert_eq!(managed_biguint!(expected_reward_per_unit), reward_per_epoch_per_nonce);
+        })
+        .assert_ok();
+
+    check_invariants(setup);
+}
+
+fn sponsor_reward<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    sponsor: &Address,
+    amount: u64,
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_esdt_transfer(sponsor, &setup.staking_sc_wrapper, SECOND_REWARD_TOKEN, 0u64, &rust_biguint!(amount), |sc| {
+            sc.sponsor_reward();
+        })
+}
+
+fn deposit_season_esdt_rewards<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    season_id: u32,
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_esdt_transfer(&setup.owner_address, &setup.staking_sc_wrapper, REWARD_TOKEN, 0u64, &rust_biguint!(REWARD_AMOUNT), |sc| {
+            sc.deposit_season_reward(season_id);
+        })
+}
+
+fn claim_season_rewards<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    season_id: u32,
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.claim_season_reward(season_id);
+        })
+}
+
+fn trigger_end_stake<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.end_staking();
+        })
+        .assert_ok();
+
+    check_invariants(setup);
+}
+
+fn claim_rewards_and_check_balance<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    expected_total_egld_reward: u64,
+    expected_total_esdt_reward: u64,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    let zero = num_bigint::ToBigUint::to_biguint(&0).unwrap();
+
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &zero, |sc| {
+            sc.claim_reward();
+        })
+        .assert_ok();
+
+    let egld_balance = setup.blockchain_wrapper
+        .get_egld_balance(caller);
+    let esdt_balance = setup.blockchain_wrapper
+        .get_esdt_balance(caller, REWARD_TOKEN, 0u64);
 
     let expected_egld_balance = num_bigint::ToBigUint::to_biguint(&expected_total_egld_reward).unwrap();
     let expected_esdt_balance = num_bigint::ToBigUint::to_biguint(&expected_total_esdt_reward).unwrap();
 
     assert_eq!(expected_egld_balance, egld_balance);
     assert_eq!(expected_esdt_balance, esdt_balance);
+
+    check_invariants(setup);
+}
+
+fn check_solvency_report<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    expected: &[(&[u8], u64, u64)],
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            let report: Vec<_> = sc.get_solvency_report().into_iter().collect();
+            assert_eq!(expected.len(), report.len());
+            for (report_line, (token, expected_owed, expected_balance)) in report.into_iter().zip(expected.iter()) {
+                let (token_identifier, owed, balance) = report_line.into_tuple();
+                assert_eq!(EgldOrEsdtTokenIdentifier::parse(managed_buffer!(token)), token_identifier);
+                assert_eq!(managed_biguint!(*expected_owed), owed);
+                assert_eq!(managed_biguint!(*expected_balance), balance);
+            }
+        })
+        .assert_ok();
+}
+
+fn seed_total_owed<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    addresses: &[&Address],
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut seeded_addresses = MultiValueEncoded::new();
+            for address in addresses.iter() {
+                seeded_addresses.push(managed_address!(*address));
+            }
+            sc.seed_total_owed(seeded_addresses);
+        })
+}
+
+fn set_staking_limits<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    max_nfts_per_address: u64,
+    max_total_staked: u64,
+    min_nfts_per_stake: u64,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.set_staking_limits(max_nfts_per_address, max_total_staked, min_nfts_per_stake);
+        })
+        .assert_ok();
+}
+
+fn distribute<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    token: &[u8],
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.distribute(EgldOrEsdtTokenIdentifier::parse(managed_buffer!(token)));
+        })
+}
+
+fn create_proposal<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    end_epoch: u64,
+    weight_by_duration: bool,
+) -> u64
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    let mut proposal_id = 0u64;
+    setup.blockchain_wrapper
+        .execute_tx(&setup.owner_address, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            let mut options = MultiValueEncoded::new();
+            options.push(managed_buffer!(b"yes"));
+            options.push(managed_buffer!(b"no"));
+            proposal_id = sc.create_proposal(managed_buffer!(b"proposal"), end_epoch, weight_by_duration, options);
+        })
+        .assert_ok();
+    proposal_id
+}
+
+fn vote<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    caller: &Address,
+    proposal_id: u64,
+    option_index: usize,
+) -> TxResult
+where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_tx(caller, &setup.staking_sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.vote(proposal_id, option_index);
+        })
+}
+
+fn check_proposal_results<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+    proposal_id: u64,
+    expected_votes: &[u64],
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            let votes: Vec<u64> = sc.get_proposal_results(proposal_id).into_iter().map(|result| result.into_tuple().1).collect();
+            assert_eq!(expected_votes.to_vec(), votes);
+        })
+        .assert_ok();
+}
+
+// owed rewards must always be covered by the contract balance and every staked nonce must be held by the contract
+fn check_invariants<StakingObjBuilder>(
+    setup: &mut StakingSetup<StakingObjBuilder>,
+) where
+    StakingObjBuilder: 'static + Copy + Fn() -> cnuns_staking::ContractObj<DebugApi>,
+{
+    setup.blockchain_wrapper
+        .execute_query(&setup.staking_sc_wrapper, |sc| {
+            for report_line in sc.get_solvency_report().into_iter() {
+                let (_, owed, balance) = report_line.into_tuple();
+                assert!(owed <= balance, "owed rewards exceed contract balance");
+            }
+
+            let stake_token = EgldOrEsdtTokenIdentifier::esdt(sc.stake_token().get());
+            let mut total_staked = 0u64;
+            let mut staked_per_nonce = std::collections::HashMap::new();
+            for (address, staked_nfts) in sc.staked_nfts().iter() {
+                let mut staked_per_address = std::collections::HashMap::new();
+                for nft in staked_nfts.iter() {
+                    *staked_per_address.entry(nft.nonce).or_insert(0u64) += nft.amount;
+                    *staked_per_nonce.entry(nft.nonce).or_insert(0u64) += nft.amount;
+                    total_staked += nft.amount;
+                }
+                for (nonce, amount) in staked_per_address {
+                    assert_eq!(amount, sc.staked_nonce_amount(&address, nonce).get(), "staked nonce amount is out of sync");
+                    assert!(sc.nonce_stakers(nonce).contains(&address), "nonce stakers index is out of sync");
+                }
+            }
+            for (nonce, amount) in staked_per_nonce {
+                let balance = sc.blockchain().get_sc_balance(&stake_token, nonce);
+                assert!(managed_biguint!(amount) <= balance, "staked nonce is not held by the contract");
+            }
+            assert_eq!(total_staked, sc.total_staked().get(), "total staked is out of sync");
+        })
+        .assert_ok();
 }
 
 fn check_total_payable_epochs<StakingObjBuilder>(
@@ -7,20 +7,126 @@
 elrond_wasm_node::wasm_endpoints! {
     cnuns_staking
     (
+        addDeniedAttributePrefix
+        addDeniedNonces
+        addEligibleNonceRange
+        addIntegrator
+        addSponsorTokens
+        addSponsors
+        allowRewardToken
+        approveIntegrator
+        backfillRewardTokens
+        cancelRewardSchedule
         claimReward
+        claimRewardTo
+        claimSeasonReward
+        claimSwappedReward
+        clearEligibleNonceRanges
+        closeTotalOwedSeeding
+        createProposal
+        createSeason
+        depositPrizes
         depositReward
+        depositRewards
+        depositSeasonReward
+        disableOpenSponsoring
+        disallowRewardToken
+        distribute
+        distributePrizes
         endStaking
+        exit
+        fundRewardSchedule
+        getAverageRewardPerNftPerEpoch
+        getActiveSetBonuses
+        getAllRewardPaymentInfo
+        getAllowedRewardTokens
         getClaimableRewards
+        getDeniedAttributePrefixes
+        getDeniedNonces
+        getEligibleNonceRanges
         getEligibleTickets
+        getEligibleTicketsSnapshot
         getFullStakeInfo
+        getHistoricalEligibleTickets
+        getIntegrators
+        getLastPrizeDistributionEpoch
+        getLastProposalId
+        getLastRaffleEpoch
+        getLastRaffleId
+        getLastRewardPerNftPerEpoch
+        getLastSeasonId
+        getLifetimeEarnings
+        getNonceLockedUntil
+        getNonceOwner
+        getNonceStakers
         getOriginEpoch
+        getPreferredRewardToken
+        getPrizeInventory
+        getProposal
+        getProposalResults
+        getRaffle
+        getRecentRewardDeposits
+        getReferralBonusPercentage
+        getReferralCount
+        getReferralEarnings
+        getReferrals
+        getReferrer
+        getRewardDepositHistory
+        getRewardDepositHistoryLength
+        getRewardDestination
         getRewardPaymentInfo
+        getRewardSchedule
+        getRewardTokens
+        getRewardTotals
+        getSeason
+        getSeasonClaimableRewards
+        getSeasonRecentRewardDeposits
+        getSeasonRewardPaymentInfo
+        getSeasonRewardTokens
+        getSolvencyReport
+        getSponsorFeePercentage
+        getSponsorTokens
+        getSponsors
+        getStakeCheckpoints
+        getStakeStatus
         getStakeToken
         getStakedNfts
+        getStakedNonceAmount
+        getStakingLimits
+        getStakingSet
+        getStakingSetIds
+        getSwapPair
+        getTicketShare
         getTotalEligibleTickets
+        getTotalStaked
+        getVotingWeight
+        integratorUnstake
+        isIntegratorApproved
+        isTotalOwedSeedingClosed
+        migrateLegacyStakes
+        previewReward
+        raffle
+        removeDeniedAttributePrefix
+        removeDeniedNonces
+        removeIntegrator
+        removeSponsorTokens
+        removeSponsors
+        removeStakingSet
+        removeSwapPair
+        revokeIntegrator
+        seedTotalOwed
+        setPreferredRewardToken
+        setReferralBonusPercentage
+        setRewardDestination
+        setSponsorFeePercentage
+        setStakingLimits
+        setStakingSet
+        setSwapPair
+        sponsorReward
         stake
         unstake
+        unstakeAndClaim
+        vote
+        callBack
     )
 }
-
-elrond_wasm_node::wasm_empty_callback! {}